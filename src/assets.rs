@@ -0,0 +1,585 @@
+/*  The asset stage: everything that happens to stylesheets and scripts on their way to the output directory.
+    It's all off by default, and turned on in sitix.toml:
+
+[assets]
+bundle = true      # inline local @import rules into the stylesheet that imports them. Stylesheets starting with _ become import-only partials.
+minify = true      # strip comments and needless whitespace from CSS and JavaScript
+fingerprint = true # put a hash of the content in the file name: css/site.css becomes css/site.0123abcd.css
+
+    Bundling rewrites relative url(...)s in imported files so they still point at the same thing from the stylesheet they end up in.
+    A JavaScript template literal is kept exactly as written, ${...} and all.
+    Since fingerprinting changes file names, templates should use [@asset_url /css/site.css] instead of writing paths by hand; it
+    resolves to whatever name the file actually ended up with (or the path you gave it, if it isn't a known asset).
+*/
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+
+pub enum Asset {
+    Copy, // nothing to do here: copy the file as-is
+    Partial, // only exists to be imported; don't write it at all
+    Built (PathBuf, Vec<u8>) // write this data to this path (relative to the output directory) instead
+}
+
+
+#[derive(Default)]
+pub struct AssetMap {
+    urls : HashMap<String, String> // original path -> final path, both relative to the output directory and with / separators
+}
+
+
+impl AssetMap {
+    pub fn insert(&mut self, original : &Path, built : &Path) {
+        self.urls.insert(url_path(original), url_path(built));
+    }
+
+    pub fn url(&self, path : &str) -> String { // map a site-root-relative path to its fingerprinted version, keeping a leading / if there was one
+        let path = path.trim();
+        let (slash, bare) = match path.strip_prefix('/') {
+            Some(bare) => ("/", bare),
+            None => ("", path)
+        };
+        match self.urls.get(bare) {
+            Some(url) => format!("{}{}", slash, url),
+            None => path.to_string()
+        }
+    }
+}
+
+
+fn url_path(path : &Path) -> String {
+    path.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/")
+}
+
+
+pub fn process(settings : &crate::config::AssetSettings, source : &Path, output : &Path) -> Result<Asset, Box<dyn std::error::Error + 'static>> {
+    // Run one asset through the pipeline. output is where it would go if it were copied as-is, relative to the output directory.
+    let extension = source.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let is_css = extension == "css";
+    let is_js = extension == "js" || extension == "mjs";
+    if !(is_css || is_js) || !(settings.bundle || settings.minify || settings.fingerprint) {
+        return Ok(Asset::Copy);
+    }
+    if is_css && settings.bundle && source.file_name().unwrap().to_string_lossy().starts_with('_') {
+        return Ok(Asset::Partial);
+    }
+    let mut text = std::fs::read_to_string(source)?;
+    if is_css && settings.bundle {
+        let mut kept = vec![];
+        let body = bundle_css(source.parent().unwrap(), source, &text, &mut vec![source.canonicalize()?], &mut kept)?;
+        text = kept.join("\n") + &body; // @import is only allowed at the top, so the ones we couldn't inline go first
+
+    }
+    if settings.minify {
+        text = if is_css { minify_css(&text) } else { minify_js(&text) };
+    }
+    let mut path = output.to_path_buf();
+    if settings.fingerprint {
+        let hash = format!("{:016x}", content_hash(text.as_bytes()));
+        let name = match (output.file_stem(), output.extension()) {
+            (Some(stem), Some(extension)) => format!("{}.{}.{}", stem.to_string_lossy(), &hash[..8], extension.to_string_lossy()),
+            _ => format!("{}.{}", output.file_name().unwrap().to_string_lossy(), &hash[..8])
+        };
+        path.set_file_name(name);
+    }
+    Ok(Asset::Built(path, text.into_bytes()))
+}
+
+
+pub fn content_hash(data : &[u8]) -> u64 { // 64-bit FNV-1a. Not cryptographic, but stable between builds and Rust versions, which is what matters.
+    let mut hash : u64 = 0xcbf29ce484222325;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+
+fn bundle_css(root : &Path, path : &Path, text : &str, stack : &mut Vec<PathBuf>, kept : &mut Vec<String>) -> Result<String, Box<dyn std::error::Error + 'static>> {
+    // Replace every @import of a local file with the contents of that file, recursively. Remote imports (anything with a scheme or
+    // starting with //) and imports with media queries can't be inlined; they're pulled out into kept instead.
+    // root is the directory of the stylesheet being built; relative URLs in files from elsewhere are rewritten to work from there.
+    // stack holds the files we're currently inside, to catch cycles.
+    let prefix = rebase_prefix(root, path.parent().unwrap());
+    let mut ret = String::new();
+    let mut rest = text;
+    while let Some(start) = find_import(rest) {
+        ret += &rebase_urls(&rest[..start], &prefix);
+        let statement_end = match rest[start..].find(';') {
+            Some(end) => start + end + 1,
+            None => rest.len()
+        };
+        let statement = &rest[start..statement_end];
+        match import_target(statement) {
+            Some(target) => {
+                let mut imported = path.parent().unwrap().to_path_buf();
+                imported.push(&target);
+                if !imported.exists() && imported.extension().is_none() {
+                    imported.set_extension("css");
+                }
+                if !imported.exists() { // SCSS-style: @import "vars" can mean _vars.css
+                    let partial = imported.with_file_name(format!("_{}", imported.file_name().unwrap().to_string_lossy()));
+                    if partial.exists() {
+                        imported = partial;
+                    }
+                }
+                let canonical = imported.canonicalize().map_err(|e| format!("{}: can't import {}: {}", path.display(), target, e))?;
+                if stack.contains(&canonical) {
+                    return Err(format!("{}: import cycle through {}", path.display(), target).into());
+                }
+                stack.push(canonical);
+                ret += &bundle_css(root, &imported, &std::fs::read_to_string(&imported)?, stack, kept)?;
+                stack.pop();
+            },
+            None => {
+                kept.push(rebase_urls(statement, &prefix));
+            }
+        }
+        rest = &rest[statement_end..];
+    }
+    ret += &rebase_urls(rest, &prefix);
+    Ok(ret)
+}
+
+
+fn normalize(path : &Path) -> Vec<String> { // a path's components with . and .. worked out, without touching the filesystem
+    let mut ret : Vec<String> = vec![];
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => {},
+            std::path::Component::ParentDir if ret.last().is_some_and(|last| last != "..") => {
+                ret.pop();
+            },
+            component => ret.push(component.as_os_str().to_string_lossy().to_string())
+        }
+    }
+    ret
+}
+
+
+fn rebase_prefix(root : &Path, directory : &Path) -> String { // how to get from root to directory, as the start of a URL: "partials/", "../shared/", or ""
+    let (root, directory) = (normalize(root), normalize(directory));
+    let common = root.iter().zip(&directory).take_while(|(a, b)| a == b).count();
+    std::iter::repeat_n("..", root.len() - common).chain(directory[common..].iter().map(|part| part.as_str())).map(|part| format!("{}/", part)).collect()
+}
+
+
+fn rebase_url(url : &str, prefix : &str) -> String { // a URL relative to an imported file -> the same URL relative to the bundle
+    if prefix.is_empty() || url.is_empty() || url.starts_with(['/', '#']) || url.contains(':') { // root-relative, a fragment, data: or a full URL
+        return url.to_string();
+    }
+    let end = url.find(['?', '#']).unwrap_or(url.len());
+    let joined = format!("{}{}", prefix, &url[..end]);
+    let mut parts : Vec<&str> = vec![];
+    for part in joined.split('/') {
+        match part {
+            "." => {},
+            ".." if parts.last().is_some_and(|last| *last != "..") => {
+                parts.pop();
+            },
+            part => parts.push(part)
+        }
+    }
+    format!("{}{}", parts.join("/"), &url[end..])
+}
+
+
+fn rebase_urls(text : &str, prefix : &str) -> String { // rebase_url every url(...), and the target of a quoted @import, outside comments and strings
+    if prefix.is_empty() {
+        return text.to_string();
+    }
+    let mut ret = String::new();
+    let mut at = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let value = if c == '/' && text[i..].starts_with("/*") {
+            let end = text[i + 2..].find("*/").map(|end| i + 2 + end + 2).unwrap_or(text.len());
+            while chars.peek().is_some_and(|(j, _)| *j < end) {
+                chars.next();
+            }
+            None
+        }
+        else if c == '"' || c == '\'' {
+            let end = string_end(text, i);
+            while chars.peek().is_some_and(|(j, _)| *j < end) {
+                chars.next();
+            }
+            None
+        }
+        else if text[i..].get(..4).is_some_and(|start| start.eq_ignore_ascii_case("url(")) {
+            let start = i + 4 + (text[i + 4..].len() - text[i + 4..].trim_start().len());
+            let end = if text[start..].starts_with(['"', '\'']) { string_end(text, start) } else { text[start..].find(')').map(|end| start + end).unwrap_or(text.len()) };
+            Some((start, end))
+        }
+        else if text[i..].starts_with("@import") && text[i + 7..].trim_start().starts_with(['"', '\'']) {
+            let start = i + 7 + (text[i + 7..].len() - text[i + 7..].trim_start().len());
+            Some((start, string_end(text, start)))
+        }
+        else {
+            None
+        };
+        if let Some((start, end)) = value {
+            let quoted = text[start..end].starts_with(['"', '\'']) && end - start >= 2;
+            let (inner_start, inner_end) = if quoted { (start + 1, end - 1) } else { (start, end) };
+            ret += &text[at..inner_start];
+            ret += &rebase_url(text[inner_start..inner_end].trim_end(), prefix);
+            ret += &text[inner_start + text[inner_start..inner_end].trim_end().len()..inner_end];
+            at = inner_end;
+            while chars.peek().is_some_and(|(j, _)| *j < end) {
+                chars.next();
+            }
+        }
+    }
+    ret += &text[at..];
+    ret
+}
+
+
+fn string_end(text : &str, start : usize) -> usize { // text[start] is a quote; just past the one that closes it
+    let quote = text[start..].chars().next().unwrap();
+    let mut escaped = false;
+    for (i, c) in text[start + 1..].char_indices() {
+        if escaped {
+            escaped = false;
+        }
+        else if c == '\\' {
+            escaped = true;
+        }
+        else if c == quote {
+            return start + 1 + i + 1;
+        }
+    }
+    text.len()
+}
+
+
+fn find_import(text : &str) -> Option<usize> { // where the next real @import starts: not inside a comment or a string
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c == '/' && chars.peek().is_some_and(|(_, next)| *next == '*') {
+            let end = text[i + 2..].find("*/")?;
+            while chars.peek().is_some_and(|(j, _)| *j < i + 2 + end + 2) {
+                chars.next();
+            }
+        }
+        else if c == '"' || c == '\'' {
+            while let Some((_, inside)) = chars.next() {
+                if inside == '\\' {
+                    chars.next();
+                }
+                else if inside == c {
+                    break;
+                }
+            }
+        }
+        else if text[i..].starts_with("@import") {
+            return Some(i);
+        }
+    }
+    None
+}
+
+
+fn import_target(statement : &str) -> Option<String> { // pull the path out of @import "x.css"; or @import url(x.css); if it's something we can inline
+    let inner = statement.strip_prefix("@import")?.trim().trim_end_matches(';').trim();
+    let (target, rest) = if let Some(inner) = inner.strip_prefix("url(") {
+        let end = inner.find(')')?;
+        (inner[..end].trim().trim_matches(|c| c == '"' || c == '\''), inner[end + 1..].trim())
+    }
+    else {
+        let quote = inner.chars().next()?;
+        if quote != '"' && quote != '\'' {
+            return None;
+        }
+        let end = inner[1..].find(quote)? + 1;
+        (&inner[1..end], inner[end + 1..].trim())
+    };
+    if !rest.is_empty() || target.contains("://") || target.starts_with("//") || target.starts_with('/') {
+        return None;
+    }
+    Some(target.to_string())
+}
+
+
+pub fn minify_css(text : &str) -> String {
+    // Drop comments, collapse whitespace, and remove it entirely next to punctuation where it can't matter. Strings are left alone.
+    // Whitespace before a colon is kept, because "a :hover" and "a:hover" are different selectors.
+    let chars : Vec<char> = text.chars().collect();
+    let mut ret = String::new();
+    let mut i = 0;
+    let mut pending_space = false;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+            pending_space = true;
+            continue;
+        }
+        if c.is_whitespace() {
+            pending_space = true;
+            i += 1;
+            continue;
+        }
+        if pending_space {
+            pending_space = false;
+            let last = ret.chars().last();
+            if !ret.is_empty() && !"{};,".contains(c) && !matches!(last, Some('{') | Some('}') | Some(';') | Some(',') | Some(':')) {
+                ret.push(' ');
+            }
+        }
+        if c == '}' && ret.ends_with(';') {
+            ret.pop();
+        }
+        if c == '"' || c == '\'' {
+            ret.push(c);
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                if chars[i] == '\\' && i + 1 < chars.len() {
+                    ret.push(chars[i]);
+                    i += 1;
+                }
+                ret.push(chars[i]);
+                i += 1;
+            }
+            if i < chars.len() {
+                ret.push(c);
+            }
+            i += 1;
+            continue;
+        }
+        ret.push(c);
+        i += 1;
+    }
+    ret
+}
+
+
+fn is_word(c : char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$' || c == '\\' || !c.is_ascii()
+}
+
+
+pub fn minify_js(text : &str) -> String {
+    // A JSMin-style minifier: it removes comments and whitespace but never renames or restructures anything. Line breaks are kept
+    // wherever automatic semicolon insertion could care about them, so it's safe on code that leans on ASI.
+    let chars : Vec<char> = text.chars().collect();
+    let mut ret = String::new();
+    let mut i = 0;
+    let mut pending_space = false;
+    let mut pending_newline = false;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+        if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                if chars[i] == '\n' {
+                    pending_newline = true;
+                }
+                i += 1;
+            }
+            i += 2;
+            pending_space = true;
+            continue;
+        }
+        if c.is_whitespace() {
+            if c == '\n' || c == '\r' {
+                pending_newline = true;
+            }
+            pending_space = true;
+            i += 1;
+            continue;
+        }
+        if pending_space {
+            let last = ret.chars().last().unwrap_or(';');
+            if pending_newline && (is_word(last) || "}])+-\"'`".contains(last)) && (is_word(c) || "{[(+-!~\"'`/".contains(c)) {
+                ret.push('\n');
+            }
+            else if (is_word(last) && is_word(c)) || (last == '+' && c == '+') || (last == '-' && c == '-') || (last == '/' && c == '/') {
+                ret.push(' ');
+            }
+            pending_space = false;
+            pending_newline = false;
+        }
+        if c == '`' {
+            let end = template_end(&chars, i);
+            ret.extend(&chars[i..end]);
+            i = end;
+            continue;
+        }
+        if c == '"' || c == '\'' {
+            ret.push(c);
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                if chars[i] == '\\' && i + 1 < chars.len() {
+                    ret.push(chars[i]);
+                    i += 1;
+                }
+                ret.push(chars[i]);
+                i += 1;
+            }
+            if i < chars.len() {
+                ret.push(c);
+            }
+            i += 1;
+            continue;
+        }
+        if c == '/' && regex_allowed(&ret) {
+            let mut in_class = false;
+            ret.push(c);
+            i += 1;
+            while i < chars.len() && (in_class || chars[i] != '/') && chars[i] != '\n' {
+                if chars[i] == '\\' && i + 1 < chars.len() {
+                    ret.push(chars[i]);
+                    i += 1;
+                }
+                else if chars[i] == '[' {
+                    in_class = true;
+                }
+                else if chars[i] == ']' {
+                    in_class = false;
+                }
+                ret.push(chars[i]);
+                i += 1;
+            }
+            if i < chars.len() && chars[i] == '/' {
+                ret.push('/');
+                i += 1;
+            }
+            continue;
+        }
+        ret.push(c);
+        i += 1;
+    }
+    ret
+}
+
+
+fn template_end(chars : &[char], start : usize) -> usize { // chars[start] opens a template literal; just past the ` that closes it
+    // ${...} can hold any expression, including strings, braces and other template literals, so it's skipped a brace at a time.
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            '`' => return i + 1,
+            '$' if chars.get(i + 1) == Some(&'{') => {
+                i += 2;
+                let mut depth = 1;
+                while i < chars.len() && depth > 0 {
+                    match chars[i] {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        '`' => {
+                            i = template_end(chars, i);
+                            continue;
+                        },
+                        quote @ ('"' | '\'') => {
+                            i += 1;
+                            while i < chars.len() && chars[i] != quote {
+                                i += if chars[i] == '\\' { 2 } else { 1 };
+                            }
+                        },
+                        _ => {}
+                    }
+                    i += 1;
+                }
+            },
+            _ => i += 1
+        }
+    }
+    chars.len()
+}
+
+
+fn regex_allowed(before : &str) -> bool { // could a / right after this be the start of a regex literal, rather than division?
+    let trimmed = before.trim_end();
+    match trimmed.chars().last() {
+        None => true,
+        Some(c) if "(,=:[!&|?{};+-*%<>~^".contains(c) => true,
+        Some(c) if is_word(c) => {
+            let word : String = trimmed.chars().rev().take_while(|c| is_word(*c)).collect::<Vec<_>>().into_iter().rev().collect();
+            matches!(word.as_str(), "return" | "typeof" | "case" | "do" | "else" | "in" | "of" | "void" | "yield" | "delete" | "throw" | "new")
+        },
+        _ => false
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn css_minifies() {
+        assert_eq!(minify_css("/* header */\na :hover ,\n b {\n  color : red ;\n  content: \"  two  spaces  \";\n}\n"), "a :hover,b{color :red;content:\"  two  spaces  \"}");
+        assert_eq!(minify_css("@media (min-width: 600px) { p { margin: 0 auto; } }"), "@media (min-width:600px){p{margin:0 auto}}");
+    }
+
+    #[test]
+    fn js_minifies() {
+        assert_eq!(minify_js("// comment\nlet a = 1 /* two */ + 2;\nlet s = \"a  b // not a comment\";"), "let a=1+2;let s=\"a  b // not a comment\";");
+        assert_eq!(minify_js("let a = b\n++c\nreturn x"), "let a=b\n++c\nreturn x"); // line breaks that ASI cares about stay
+        assert_eq!(minify_js("a = b / c / d; r = /[/]\\d+ /g.test(s)"), "a=b/c/d;r=/[/]\\d+ /g.test(s)");
+        assert_eq!(minify_js("x = a + +b - -c"), "x=a+ +b- -c");
+    }
+
+    #[test]
+    fn template_literals_are_kept_whole() {
+        assert_eq!(minify_js("let s = `a  ${ b ? `c  ${d}  e` : '}' }  f`;\nlet t = 1 ;"), "let s=`a  ${ b ? `c  ${d}  e` : '}' }  f`;let t=1;");
+        assert_eq!(minify_js("x = `${ {a: 1}.a }  \\`  `  ;"), "x=`${ {a: 1}.a }  \\`  `;");
+    }
+
+    #[test]
+    fn regexes_are_told_from_division() {
+        assert!(regex_allowed(""));
+        assert!(regex_allowed("x = "));
+        assert!(regex_allowed("f("));
+        assert!(regex_allowed("return "));
+        assert!(!regex_allowed("a "));
+        assert!(!regex_allowed("returned"));
+        assert!(!regex_allowed("f(x)"));
+    }
+
+    #[test]
+    fn imports_in_comments_and_strings_are_left_alone() {
+        let directory = std::env::temp_dir().join(format!("sitix-assets-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("_vars.css"), ":root { --x: 1; }\n").unwrap();
+        let site = directory.join("site.css");
+        let text = "/* @import \"nope.css\"; */\n@import \"vars\";\n@import url(https://fonts.example/a.css);\np::after { content: \"@import 'nope.css';\"; }\n";
+        let mut kept = vec![];
+        let bundled = bundle_css(&directory, &site, text, &mut vec![], &mut kept);
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(bundled.unwrap(), "/* @import \"nope.css\"; */\n:root { --x: 1; }\n\n\np::after { content: \"@import 'nope.css';\"; }\n");
+        assert_eq!(kept, vec!["@import url(https://fonts.example/a.css);"]);
+    }
+
+    #[test]
+    fn bundled_urls_are_rebased() {
+        let directory = std::env::temp_dir().join(format!("sitix-rebase-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("css/parts/deep")).unwrap();
+        std::fs::write(directory.join("css/parts/_a.css"), "@import \"deep/b\";\n@import 'print.css' print;\n\
+            a { background: url(../img/a.png), URL( \"b.png?v=1#x\" ) } /* url(c.png) */ b { content: \"url(d.png)\"; mask: url(data:image/png;base64,AA) url(/e.png) url(#f) }").unwrap();
+        std::fs::write(directory.join("css/parts/deep/_b.css"), "c { background: url('../../../img/c.png') }\n").unwrap();
+        let site = directory.join("css/site.css");
+        let mut kept = vec![];
+        let bundled = bundle_css(&directory.join("css"), &site, "@import \"parts/a\";\nd { background: url(d.png) }", &mut vec![], &mut kept);
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(bundled.unwrap(), "c { background: url('../img/c.png') }\n\n\n\
+            a { background: url(img/a.png), URL( \"parts/b.png?v=1#x\" ) } /* url(c.png) */ b { content: \"url(d.png)\"; mask: url(data:image/png;base64,AA) url(/e.png) url(#f) }\n\
+            d { background: url(d.png) }");
+        assert_eq!(kept, vec!["@import 'parts/print.css' print;"]);
+        assert_eq!(rebase_prefix(Path::new("site/css"), Path::new("site/css/../shared/./x")), "../shared/x/");
+    }
+}
@@ -0,0 +1,332 @@
+/*  Project configuration, loaded from sitix.toml in the project root. Every field is optional:
+
+source = "."                    # where the pages live
+output = "output"               # where the rendered site goes
+templates = "_templates"        # where the templates live
+default_template = "default"    # used by pages that don't set content.template
+base_url = "https://example.com"
+title = "My Site"
+pretty_urls = false             # write about.html (or about.md) to about/index.html
+minify_html = false             # minify rendered HTML pages
+sitemap = true                  # write sitemap.xml (needs base_url)
+copy_extensions = ["js"]        # always copy these, even if they look like Rasta (images, fonts, media and archives always are)
+ignore = ["README.md", "*.psd"] # extra .sitixignore patterns
+include = [".well-known"]       # extra .sitixignore includes (the ! patterns)
+
+[assets]                        # see assets.rs
+bundle = false
+minify = false
+fingerprint = false
+
+[images]                        # see images.rs
+widths = [480, 960, 1600]
+format = "webp"
+cache = ".sitix-cache"
+
+[highlight]                     # see highlight.rs
+theme = "InspiredGitHub"
+stylesheet = "highlight.css"
+
+[[feeds]]                       # see feeds.rs
+collection = "posts"
+rss = "posts/feed.xml"
+
+[[taxonomies]]                  # see taxonomy.rs; tags and categories are there by default
+name = "tags"
+template = "taxonomy"
+path = "tags"
+
+[i18n]                          # see i18n.rs; leave languages empty for a single-language site
+default_language = "en"
+languages = ["en", "fr"]
+strings = "_i18n"
+prefix_default = false
+
+[lint]                          # see lint.rs
+allow = ["unused-assignment"]   # warnings not to bother with
+
+[plugins.whatever]              # free-form settings, for things that want them
+key = "value"
+
+    Relative paths are relative to the project root, not to wherever sitix was run from. Command line flags override the file.
+    Rasta can see some of these under the site scope: source, output, templates, default_template, base_url, title, pretty_urls,
+    default_language, languages (a list, [*l site.languages-]), and plugins, with its tables as dots ([^site.plugins.whatever.key]).
+    The rest (minify_html, sitemap, the ignore lists, and the assets, images, highlight, feeds, taxonomies and lint tables) only
+    change how sitix builds the site.
+*/
+
+use crate::rasta::Scope;
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+
+#[derive(serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub source : PathBuf,
+    pub output : PathBuf,
+    pub templates : PathBuf,
+    pub default_template : String,
+    pub base_url : Option<String>,
+    pub title : Option<String>,
+    pub pretty_urls : bool,
+    pub minify_html : bool,
+    pub sitemap : bool,
+    pub copy_extensions : Vec<String>,
+    pub ignore : Vec<String>,
+    pub include : Vec<String>,
+    pub assets : AssetSettings,
+    pub images : ImageSettings,
+    pub highlight : HighlightSettings,
+    pub feeds : Vec<FeedSettings>,
+    pub taxonomies : Vec<TaxonomySettings>,
+    pub i18n : I18nSettings,
+    pub lint : LintSettings,
+    pub plugins : toml::Table
+}
+
+
+#[derive(serde::Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AssetSettings {
+    pub bundle : bool,
+    pub minify : bool,
+    pub fingerprint : bool
+}
+
+
+#[derive(serde::Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImageSettings {
+    pub widths : Vec<u32>,
+    pub format : String,
+    pub cache : PathBuf
+}
+
+
+impl Default for ImageSettings {
+    fn default() -> Self {
+        Self {
+            widths : vec![480, 960, 1600],
+            format : "webp".to_string(),
+            cache : PathBuf::from(".sitix-cache")
+        }
+    }
+}
+
+
+#[derive(serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HighlightSettings {
+    pub theme : String,
+    pub stylesheet : PathBuf
+}
+
+
+impl Default for HighlightSettings {
+    fn default() -> Self {
+        Self {
+            theme : "InspiredGitHub".to_string(),
+            stylesheet : PathBuf::from("highlight.css")
+        }
+    }
+}
+
+
+#[derive(serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeedSettings {
+    pub collection : PathBuf,
+    pub title : Option<String>,
+    pub description : String,
+    pub author : Option<String>,
+    pub rss : Option<PathBuf>,
+    pub atom : Option<PathBuf>,
+    pub limit : usize
+}
+
+
+impl Default for FeedSettings {
+    fn default() -> Self {
+        Self {
+            collection : PathBuf::new(),
+            title : None,
+            description : String::new(),
+            author : None,
+            rss : None,
+            atom : None,
+            limit : 20
+        }
+    }
+}
+
+
+#[derive(serde::Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TaxonomySettings {
+    pub name : String,
+    #[serde(default = "TaxonomySettings::default_template")]
+    pub template : String,
+    #[serde(default)]
+    pub path : PathBuf // defaults to the name
+}
+
+
+impl TaxonomySettings {
+    pub fn named(name : &str) -> Self {
+        Self {
+            name : name.to_string(),
+            template : Self::default_template(),
+            path : PathBuf::from(name)
+        }
+    }
+
+    fn default_template() -> String {
+        "taxonomy".to_string()
+    }
+}
+
+
+#[derive(serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct I18nSettings {
+    pub default_language : String,
+    pub languages : Vec<String>,
+    pub strings : PathBuf,
+    pub prefix_default : bool
+}
+
+
+impl Default for I18nSettings {
+    fn default() -> Self {
+        Self {
+            default_language : "en".to_string(),
+            languages : vec![],
+            strings : PathBuf::from("_i18n"),
+            prefix_default : false
+        }
+    }
+}
+
+
+#[derive(serde::Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct LintSettings {
+    pub allow : Vec<String>
+}
+
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            source : PathBuf::from("."),
+            output : PathBuf::from("output"),
+            templates : PathBuf::from("_templates"),
+            default_template : "default".to_string(),
+            base_url : None,
+            title : None,
+            pretty_urls : false,
+            minify_html : false,
+            sitemap : true,
+            copy_extensions : vec![],
+            ignore : vec![],
+            include : vec![],
+            assets : AssetSettings::default(),
+            images : ImageSettings::default(),
+            highlight : HighlightSettings::default(),
+            feeds : vec![],
+            taxonomies : vec![TaxonomySettings::named("tags"), TaxonomySettings::named("categories")],
+            i18n : I18nSettings::default(),
+            lint : LintSettings::default(),
+            plugins : toml::Table::new()
+        }
+    }
+}
+
+
+impl Config {
+    pub fn load(root : &std::path::Path) -> Result<Self, Box<dyn std::error::Error + 'static>> { // read root/sitix.toml (if it exists) and resolve its paths against root
+        let mut path = root.to_path_buf();
+        path.push("sitix.toml");
+        let mut ret : Config = if path.exists() {
+            toml::from_str(&std::fs::read_to_string(&path)?).map_err(|e| format!("{}: {}", path.display(), e))?
+        }
+        else {
+            Config::default()
+        };
+        ret.source = root.join(&ret.source).components().collect(); // collecting the components tidies up things like ./. into just .
+        ret.output = root.join(&ret.output).components().collect();
+        ret.templates = root.join(&ret.templates).components().collect();
+        ret.images.cache = root.join(&ret.images.cache).components().collect();
+        ret.i18n.strings = root.join(&ret.i18n.strings).components().collect();
+        for taxonomy in &mut ret.taxonomies {
+            if taxonomy.path.as_os_str().is_empty() {
+                taxonomy.path = PathBuf::from(&taxonomy.name);
+            }
+        }
+        Ok(ret)
+    }
+
+    pub fn fill_scope(&self, scope : Rc<RefCell<Scope>>) { // expose the config to Rasta. scope should be the site scope.
+        Scope::set_w(scope.clone(), "source", self.source.display().to_string());
+        Scope::set_w(scope.clone(), "output", self.output.display().to_string());
+        Scope::set_w(scope.clone(), "templates", self.templates.display().to_string());
+        Scope::set_w(scope.clone(), "default_template", self.default_template.clone());
+        if let Some(base_url) = &self.base_url {
+            Scope::set_w(scope.clone(), "base_url", base_url.clone());
+        }
+        if let Some(title) = &self.title {
+            Scope::set_w(scope.clone(), "title", title.clone());
+        }
+        Scope::set_w(scope.clone(), "pretty_urls", self.pretty_urls.to_string());
+        Scope::set_w(scope.clone(), "default_language", self.i18n.default_language.clone());
+        for (index, language) in self.i18n.languages.iter().enumerate() {
+            Scope::set_w(scope.clone(), &format!("languages.{}", index), language.clone());
+        }
+        for (key, value) in &self.plugins {
+            fill_value(scope.clone(), key, value);
+        }
+    }
+}
+
+
+fn fill_value(scope : Rc<RefCell<Scope>>, name : &str, value : &toml::Value) { // name is relative to site.plugins
+    match value {
+        toml::Value::String (s) => Scope::set_w(scope, &format!("plugins.{}", name), s.clone()),
+        toml::Value::Table (table) => {
+            for (key, value) in table {
+                fill_value(scope.clone(), &format!("{}.{}", name, key), value);
+            }
+        },
+        toml::Value::Array (array) => {
+            for (index, value) in array.iter().enumerate() {
+                fill_value(scope.clone(), &format!("{}.{}", name, index), value);
+            }
+        },
+        other => Scope::set_w(scope, &format!("plugins.{}", name), other.to_string())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_site_scope_sees_the_documented_settings() {
+        let config : Config = toml::from_str("title = \"Hi\"\nbase_url = \"https://example.com\"\nminify_html = true\n\
+            [i18n]\nlanguages = [\"en\", \"fr\"]\n[plugins.comments]\nlimit = 3\nenabled = true").unwrap();
+        let site = Scope::top().wrap();
+        config.fill_scope(site.clone());
+        let get = |name : &str| site.borrow().get(name.to_string());
+        assert_eq!(get("title").as_deref(), Some("Hi"));
+        assert_eq!(get("base_url").as_deref(), Some("https://example.com"));
+        assert_eq!(get("pretty_urls").as_deref(), Some("false"));
+        assert_eq!(get("default_language").as_deref(), Some("en"));
+        assert_eq!(get("languages.1").as_deref(), Some("fr"));
+        assert_eq!(get("plugins.comments.limit").as_deref(), Some("3"));
+        assert_eq!(get("plugins.comments.enabled").as_deref(), Some("true"));
+        assert_eq!(get("minify_html"), None);
+    }
+}
@@ -0,0 +1,145 @@
+/*  Just enough calendar math for sitemaps and feeds. Everything is UTC, and times are seconds since the Unix epoch.
+*/
+
+
+pub fn days_to_civil(days : i64) -> (i64, u32, u32) { // days since 1970-01-01 -> (year, month, day). Howard Hinnant's algorithm.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+
+pub fn unix_seconds(time : std::time::SystemTime) -> i64 {
+    match time.duration_since(std::time::UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64)
+    }
+}
+
+
+pub fn w3c_date(seconds : i64) -> String { // 2024-03-01, as sitemaps like it
+    let (year, month, day) = days_to_civil(seconds.div_euclid(86400));
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+
+pub fn civil_to_days(year : i64, month : u32, day : u32) -> i64 { // the inverse of days_to_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+
+fn days_in_month(year : i64, month : u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31
+    }
+}
+
+
+pub fn parse(text : &str) -> Option<i64> { // 2024-03-01, 2024-03-01 12:30, or 2024-03-01T12:30:00 with an optional Z or +02:00 offset -> unix time
+    let text = text.trim();
+    let (date, time) = match text.find(['T', ' ']) {
+        Some(split) => (&text[..split], text[split + 1..].trim()),
+        None => (text, "")
+    };
+    let mut parts = date.split('-');
+    let year : i64 = parts.next()?.parse().ok()?;
+    let month : u32 = parts.next()?.parse().ok()?;
+    let day : u32 = parts.next()?.parse().ok()?;
+    if parts.next().is_some() || !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) { // no rolling Feb 31 into March
+        return None;
+    }
+    let mut seconds = civil_to_days(year, month, day) * 86400;
+    if time.is_empty() {
+        return Some(seconds);
+    }
+    let (clock, offset) = if let Some(clock) = time.strip_suffix('Z') {
+        (clock, 0)
+    }
+    else if let Some(split) = time.rfind(['+', '-']) {
+        let sign = if time[split..].starts_with('-') { -1 } else { 1 };
+        let mut offset = time[split + 1..].split(':');
+        let hours : i64 = offset.next()?.parse().ok()?;
+        let minutes : i64 = offset.next().unwrap_or("0").parse().ok()?;
+        if offset.next().is_some() || !(0..24).contains(&hours) || !(0..60).contains(&minutes) {
+            return None;
+        }
+        (&time[..split], sign * (hours * 3600 + minutes * 60))
+    }
+    else {
+        (time, 0)
+    };
+    let mut clock = clock.split(':');
+    let hours : i64 = clock.next()?.parse().ok()?;
+    let minutes : i64 = clock.next()?.parse().ok()?;
+    let secs : f64 = clock.next().unwrap_or("0").parse().ok()?;
+    if clock.next().is_some() || !(0..24).contains(&hours) || !(0..60).contains(&minutes) || !(0.0..61.0).contains(&secs) { // 61 for leap seconds
+        return None;
+    }
+    seconds += hours * 3600 + minutes * 60 + secs as i64 - offset;
+    Some(seconds)
+}
+
+
+pub fn rfc3339(seconds : i64) -> String { // 2024-03-01T12:30:00Z, for Atom
+    let secs = seconds.rem_euclid(86400);
+    format!("{}T{:02}:{:02}:{:02}Z", w3c_date(seconds), secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+
+pub fn rfc2822(seconds : i64) -> String { // Fri, 01 Mar 2024 12:30:00 +0000, for RSS
+    const DAYS : [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"]; // 1970-01-01 was a Thursday
+    const MONTHS : [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let days = seconds.div_euclid(86400);
+    let (year, month, day) = days_to_civil(days);
+    let secs = seconds.rem_euclid(86400);
+    format!("{}, {:02} {} {:04} {:02}:{:02}:{:02} +0000", DAYS[days.rem_euclid(7) as usize], day, MONTHS[month as usize - 1], year, secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_parse() {
+        assert_eq!(parse("2024-03-01").map(w3c_date), Some("2024-03-01".to_string()));
+        assert_eq!(parse("2024-03-01T12:30:00Z").map(rfc3339), Some("2024-03-01T12:30:00Z".to_string()));
+        assert_eq!(parse("2024-03-01 12:30+02:00").map(rfc3339), Some("2024-03-01T10:30:00Z".to_string()));
+        assert_eq!(parse("1969-12-31").map(rfc2822), Some("Wed, 31 Dec 1969 00:00:00 +0000".to_string()));
+    }
+
+    #[test]
+    fn impossible_dates_are_rejected() {
+        assert_eq!(parse("2024-02-31"), None);
+        assert_eq!(parse("2023-02-29"), None);
+        assert_eq!(parse("1900-02-29"), None);
+        assert!(parse("2000-02-29").is_some());
+        assert!(parse("2024-02-29").is_some());
+        assert_eq!(parse("2024-04-31"), None);
+        assert_eq!(parse("2024-13-01"), None);
+        assert_eq!(parse("2024-01-00"), None);
+        assert_eq!(parse("yesterday"), None);
+        assert_eq!(parse("2024-03-01 25:99"), None);
+        assert_eq!(parse("2024-03-01T12:60Z"), None);
+        assert_eq!(parse("2024-03-01T12:30:75Z"), None);
+        assert_eq!(parse("2024-03-01T12:30+99:00"), None);
+        assert_eq!(parse("2024-03-01T12:30-05:75"), None);
+        assert!(parse("2024-03-01T23:59:60Z").is_some());
+    }
+}
@@ -0,0 +1,225 @@
+/*  --diff: what a build changes in the output directory, compared to what's there now. Every file is added, removed, changed or the same;
+    text files that changed get a unified diff, like diff -u between the old output and the new. With --dry-run that's a preview of a
+    deploy; without it, it's a record of what the build just did. Only files the last build generated can be removed (see output.rs),
+    so anything else in the output directory doesn't show up unless the new build overwrites it.
+*/
+
+use std::path::{Path, PathBuf};
+
+
+const CONTEXT : usize = 3; // lines of context around each hunk, like diff -u
+const MAX_TABLE : usize = 16_000_000; // past this many lines * lines, a changed file is shown as all removed and then all added
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Change {
+    Added,
+    Removed,
+    Changed
+}
+
+
+impl Change {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Change::Added => "added",
+            Change::Removed => "removed",
+            Change::Changed => "changed"
+        }
+    }
+
+    pub fn symbol(&self) -> char { // like git status --short
+        match self {
+            Change::Added => 'A',
+            Change::Removed => 'D',
+            Change::Changed => 'M'
+        }
+    }
+}
+
+
+pub struct Difference {
+    pub path : PathBuf, // relative to the output directory
+    pub change : Change,
+    pub diff : Option<String> // the unified diff, if both sides are text
+}
+
+
+fn text(data : &[u8]) -> Option<&str> { // what diff calls a text file: valid UTF-8, no NULs
+    std::str::from_utf8(data).ok().filter(|text| !text.contains('\0'))
+}
+
+
+pub fn compare(directory : &Path, previous : &[PathBuf], outputs : &[&Path], contents : &dyn Fn(&Path) -> Result<Vec<u8>, std::io::Error>) -> Result<(Vec<Difference>, usize), std::io::Error> {
+    // directory is the output directory as it is now, previous is what the last build generated there, outputs are the files this build
+    // would write, and contents gives what they'd hold. -> (every difference, sorted by path; how many files are the same)
+    let mut ret = vec![];
+    let mut unchanged = 0;
+    for path in previous {
+        if !outputs.contains(&path.as_path()) && directory.join(path).is_file() {
+            let data = std::fs::read(directory.join(path))?;
+            ret.push(Difference {
+                path : path.clone(),
+                change : Change::Removed,
+                diff : text(&data).map(|data| unified(path, data, ""))
+            });
+        }
+    }
+    for path in outputs {
+        let new = contents(path)?;
+        if !directory.join(path).is_file() {
+            ret.push(Difference {
+                path : path.to_path_buf(),
+                change : Change::Added,
+                diff : text(&new).map(|new| unified(path, "", new))
+            });
+            continue;
+        }
+        let data = std::fs::read(directory.join(path))?;
+        if data == new {
+            unchanged += 1;
+            continue;
+        }
+        ret.push(Difference {
+            path : path.to_path_buf(),
+            change : Change::Changed,
+            diff : text(&data).zip(text(&new)).map(|(old, new)| unified(path, old, new))
+        });
+    }
+    ret.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok((ret, unchanged))
+}
+
+
+fn edits<'a>(old : &[&'a str], new : &[&'a str]) -> Vec<(char, &'a str)> { // the shortest way from old to new: (' ' kept, '-' removed, '+' added; line)
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (a, b) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+    let mut ret : Vec<(char, &str)> = old[..prefix].iter().map(|line| (' ', *line)).collect();
+    let (mut i, mut j) = (0, 0);
+    if a.len() * b.len() <= MAX_TABLE {
+        let width = b.len() + 1;
+        let mut lcs = vec![0u32; (a.len() + 1) * width]; // lcs[i * width + j]: the longest common subsequence of a[i..] and b[j..]
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i * width + j] = if a[i] == b[j] { lcs[(i + 1) * width + j + 1] + 1 } else { lcs[(i + 1) * width + j].max(lcs[i * width + j + 1]) };
+            }
+        }
+        while i < a.len() && j < b.len() {
+            if a[i] == b[j] {
+                ret.push((' ', a[i]));
+                i += 1;
+                j += 1;
+            }
+            else if lcs[(i + 1) * width + j] >= lcs[i * width + j + 1] {
+                ret.push(('-', a[i]));
+                i += 1;
+            }
+            else {
+                ret.push(('+', b[j]));
+                j += 1;
+            }
+        }
+    }
+    ret.extend(a[i..].iter().map(|line| ('-', *line)));
+    ret.extend(b[j..].iter().map(|line| ('+', *line)));
+    ret.extend(old[old.len() - suffix..].iter().map(|line| (' ', *line)));
+    ret
+}
+
+
+fn range(start : usize, count : usize) -> String { // a hunk header's -start,count; an empty side is numbered from the line before it
+    if count == 0 {
+        format!("{},0", start)
+    }
+    else if count == 1 {
+        format!("{}", start + 1)
+    }
+    else {
+        format!("{},{}", start + 1, count)
+    }
+}
+
+
+pub fn unified(path : &Path, old : &str, new : &str) -> String {
+    let edits = edits(&old.split_inclusive('\n').collect::<Vec<_>>(), &new.split_inclusive('\n').collect::<Vec<_>>());
+    let changes : Vec<usize> = (0..edits.len()).filter(|&i| edits[i].0 != ' ').collect();
+    let mut ret = format!("--- a/{}\n+++ b/{}\n", path.display(), path.display());
+    let mut group = 0;
+    while group < changes.len() {
+        let mut last = group;
+        while last + 1 < changes.len() && changes[last + 1] - changes[last] <= CONTEXT * 2 + 1 {
+            last += 1;
+        }
+        let start = changes[group].saturating_sub(CONTEXT);
+        let end = (changes[last] + CONTEXT + 1).min(edits.len());
+        let old_start = edits[..start].iter().filter(|(kind, _)| *kind != '+').count();
+        let new_start = edits[..start].iter().filter(|(kind, _)| *kind != '-').count();
+        let hunk = &edits[start..end];
+        ret += &format!("@@ -{} +{} @@\n", range(old_start, hunk.iter().filter(|(kind, _)| *kind != '+').count()), range(new_start, hunk.iter().filter(|(kind, _)| *kind != '-').count()));
+        for (kind, line) in hunk {
+            ret.push(*kind);
+            ret += line;
+            if !line.ends_with('\n') {
+                ret += "\n\\ No newline at end of file\n";
+            }
+        }
+        group = last + 1;
+    }
+    ret
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(numbers : impl Iterator<Item = usize>) -> String {
+        numbers.map(|n| format!("{}\n", n)).collect()
+    }
+
+    #[test]
+    fn compare_finds_every_kind_of_change() {
+        let directory = std::env::temp_dir().join(format!("sitix-diff-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        for (path, text) in [("same.txt", "same\n"), ("changed.txt", "old\n"), ("stale.txt", "bye\n"), ("yours.txt", "not ours\n")] {
+            std::fs::write(directory.join(path), text).unwrap();
+        }
+        let previous = [PathBuf::from("same.txt"), PathBuf::from("changed.txt"), PathBuf::from("stale.txt")];
+        let outputs = [Path::new("same.txt"), Path::new("changed.txt"), Path::new("new.bin")];
+        let contents = |path : &Path| Ok(match path.to_str().unwrap() {
+            "same.txt" => b"same\n".to_vec(),
+            "changed.txt" => b"new\n".to_vec(),
+            _ => vec![0, 1, 2]
+        });
+        let (differences, unchanged) = compare(&directory, &previous, &outputs, &contents).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        let found : Vec<(&str, Change, bool)> = differences.iter().map(|d| (d.path.to_str().unwrap(), d.change, d.diff.is_some())).collect();
+        assert_eq!(found, vec![("changed.txt", Change::Changed, true), ("new.bin", Change::Added, false), ("stale.txt", Change::Removed, true)]);
+        assert_eq!(unchanged, 1);
+        assert_eq!(differences[0].diff.as_deref(), Some("--- a/changed.txt\n+++ b/changed.txt\n@@ -1 +1 @@\n-old\n+new\n"));
+    }
+
+    #[test]
+    fn added_and_removed_files() {
+        assert_eq!(unified(Path::new("a"), "", "x\ny\n"), "--- a/a\n+++ b/a\n@@ -0,0 +1,2 @@\n+x\n+y\n");
+        assert_eq!(unified(Path::new("a"), "x\n", ""), "--- a/a\n+++ b/a\n@@ -1 +0,0 @@\n-x\n");
+    }
+
+    #[test]
+    fn nearby_changes_share_a_hunk() {
+        let old = lines(1..=20);
+        let close = old.replace("\n5\n", "\nfive\n").replace("\n12\n", "\ntwelve\n"); // 6 unchanged lines apart
+        assert_eq!(unified(Path::new("a"), &old, &close).matches("@@ -").count(), 1);
+        assert!(unified(Path::new("a"), &old, &close).contains("@@ -2,14 +2,14 @@\n"));
+        let far = old.replace("\n5\n", "\nfive\n").replace("\n13\n", "\nthirteen\n"); // 7 unchanged lines apart: two hunks
+        let context = |numbers : std::ops::RangeInclusive<usize>| numbers.map(|n| format!(" {}\n", n)).collect::<String>();
+        assert_eq!(unified(Path::new("a"), &old, &far), format!("--- a/a\n+++ b/a\n@@ -2,7 +2,7 @@\n{}-5\n+five\n{}@@ -10,7 +10,7 @@\n{}-13\n+thirteen\n{}",
+            context(2..=4), context(6..=8), context(10..=12), context(14..=16)));
+    }
+
+    #[test]
+    fn missing_newlines_are_marked() {
+        assert_eq!(unified(Path::new("a"), "a\nb", "a\nc"), "--- a/a\n+++ b/a\n@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+c\n\\ No newline at end of file\n");
+    }
+}
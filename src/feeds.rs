@@ -0,0 +1,224 @@
+/*  RSS 2.0 and Atom feeds. Each feed is built from a collection: the pages under one directory of the source tree that set a date.
+    They're configured in sitix.toml, one [[feeds]] table per feed (base_url is required, since feeds need absolute URLs):
+
+[[feeds]]
+collection = "posts"           # source directory to pull pages from
+title = "My Blog"              # defaults to the site title (or, without one, the site's host name)
+description = "Things I wrote"
+author = "Me"                  # Atom wants one; defaults to the feed title
+rss = "posts/feed.xml"         # where to write the RSS feed, relative to the output directory. Leave it out for no RSS.
+atom = "posts/atom.xml"        # same, for Atom
+limit = 20                     # newest entries only. 0 means all of them.
+
+    Entries come from each page's content scope: [=title ...], [=date 2024-03-01] (or 2024-03-01T12:30:00Z), an optional [=summary ...],
+    and the rendered content itself (without the template). Links in the content (href, src, srcset and poster) are made absolute, since
+    feed readers show it somewhere else: root-relative ones against base_url, the rest against the entry's own URL. Pages without a summary
+    get the start of their text as the RSS description, since a lot of readers show that in their lists.
+*/
+
+use crate::links;
+use crate::sitemap::xml_escape;
+
+
+const EXCERPT_WORDS : usize = 50;
+const INLINE : &[&str] = &["a", "abbr", "b", "code", "em", "i", "mark", "small", "span", "strong", "sub", "sup"]; // tags that don't split words
+
+
+pub struct Entry {
+    pub url : String,
+    pub title : String,
+    pub date : i64,
+    pub summary : Option<String>,
+    pub content : String
+}
+
+
+fn join(page_url : &str, base_url : &str, url : &str) -> String { // resolve a link the way a browser would on the page itself
+    let trimmed = url.trim();
+    if trimmed.is_empty() || links::is_external(trimmed) {
+        return url.to_string();
+    }
+    let page = page_url.split('#').next().unwrap();
+    if trimmed.starts_with('#') {
+        return format!("{}{}", page, trimmed);
+    }
+    let page = page.split('?').next().unwrap();
+    if trimmed.starts_with('?') {
+        return format!("{}{}", page, trimmed);
+    }
+    if let Some(rest) = trimmed.strip_prefix('/') {
+        return format!("{}/{}", base_url.trim_end_matches('/'), rest);
+    }
+    let root = page.find("://").and_then(|scheme| page[scheme + 3..].find('/').map(|slash| scheme + 3 + slash)).unwrap_or(page.len()); // where the path starts
+    let mut segments : Vec<&str> = page[root..].split('/').skip(1).collect();
+    segments.pop(); // the page's own file name, or nothing if it's a directory
+    let end = trimmed.find(['?', '#']).unwrap_or(trimmed.len());
+    let parts : Vec<&str> = trimmed[..end].split('/').collect();
+    for (i, part) in parts.iter().enumerate() {
+        match *part {
+            "." | ".." => {
+                if *part == ".." {
+                    segments.pop();
+                }
+                if i + 1 == parts.len() { // ../ and .. both mean the directory
+                    segments.push("");
+                }
+            },
+            part => segments.push(part)
+        }
+    }
+    format!("{}/{}{}", &page[..root], segments.join("/"), &trimmed[end..])
+}
+
+
+fn absolutize(html : &str, page_url : &str, base_url : &str) -> String { // make every link in a page absolute, so it goes somewhere from a feed reader
+    let lower = html.to_ascii_lowercase(); // same byte offsets as html
+    let mut ret = String::new();
+    let mut copied = 0;
+    let mut at = 0;
+    while let Some(start) = html[at..].find('<').map(|start| at + start) {
+        if html[start..].starts_with("<!--") {
+            at = html[start..].find("-->").map(|end| start + end + 3).unwrap_or(html.len());
+            continue;
+        }
+        let name : String = lower[start + 1..].chars().take_while(|c| c.is_ascii_alphanumeric()).collect();
+        if name.is_empty() {
+            at = start + 1;
+            continue;
+        }
+        let tag = start + 1 + name.len();
+        let (attributes, end) = links::attribute_spans(&html[tag..]);
+        for (key, span) in attributes.into_iter().filter(|(key, _)| links::LINK_ATTRIBUTES.contains(&key.as_str())) {
+            let value = &html[tag + span.start..tag + span.end];
+            ret += &html[copied..tag + span.start];
+            ret += &if key == "srcset" { // url 2x, url 800w, ...
+                value.split(',').map(|candidate| {
+                    let start = candidate.len() - candidate.trim_start().len();
+                    let end = candidate[start..].find(char::is_whitespace).map(|end| start + end).unwrap_or(candidate.len());
+                    format!("{}{}{}", &candidate[..start], join(page_url, base_url, &candidate[start..end]), &candidate[end..])
+                }).collect::<Vec<_>>().join(",")
+            }
+            else {
+                join(page_url, base_url, value)
+            };
+            copied = tag + span.end;
+        }
+        at = tag + end;
+        if links::RAW_ELEMENTS.contains(&name.as_str()) {
+            at = lower[at..].find(&format!("</{}", name)).map(|close| at + close).unwrap_or(html.len());
+        }
+    }
+    ret += &html[copied..];
+    ret
+}
+
+
+fn excerpt(html : &str) -> String { // the first few words of a page's text, for when it has no summary
+    let lower = html.to_ascii_lowercase(); // same byte offsets as html
+    let mut text = String::new();
+    let mut at = 0;
+    while let Some(start) = html[at..].find('<').map(|start| at + start) {
+        text += &html[at..start];
+        let name : String = lower[start + 1..].trim_start_matches('/').chars().take_while(|c| c.is_ascii_alphanumeric()).collect();
+        if !INLINE.contains(&name.as_str()) {
+            text.push(' ');
+        }
+        let end = match name.as_str() {
+            "script" | "style" if !lower[start..].starts_with("</") => lower[start..].find(&format!("</{}", name)).map(|close| start + close), // their insides aren't text
+            _ => Some(start)
+        };
+        at = end.and_then(|end| html[end..].find('>').map(|close| end + close + 1)).unwrap_or(html.len());
+    }
+    text += &html[at..];
+    let words : Vec<&str> = text.split_whitespace().collect();
+    if words.len() > EXCERPT_WORDS {
+        format!("{}…", words[..EXCERPT_WORDS].join(" "))
+    }
+    else {
+        words.join(" ")
+    }
+}
+
+
+pub fn rss(settings : &crate::config::FeedSettings, title : &str, base_url : &str, self_url : &str, entries : &[Entry]) -> String {
+    let mut ret = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    ret += "<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\" xmlns:content=\"http://purl.org/rss/1.0/modules/content/\">\n<channel>\n";
+    ret += &format!("  <title>{}</title>\n", xml_escape(title));
+    ret += &format!("  <link>{}/</link>\n", xml_escape(base_url.trim_end_matches('/')));
+    ret += &format!("  <description>{}</description>\n", xml_escape(if settings.description.is_empty() { title } else { &settings.description })); // RSS requires one
+    ret += &format!("  <atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n", xml_escape(self_url));
+    if let Some(entry) = entries.first() {
+        ret += &format!("  <lastBuildDate>{}</lastBuildDate>\n", crate::dates::rfc2822(entry.date));
+    }
+    for entry in entries {
+        let content = absolutize(&entry.content, &entry.url, base_url);
+        ret += "  <item>\n";
+        ret += &format!("    <title>{}</title>\n", xml_escape(&entry.title));
+        ret += &format!("    <link>{}</link>\n", xml_escape(&entry.url));
+        ret += &format!("    <guid isPermaLink=\"true\">{}</guid>\n", xml_escape(&entry.url));
+        ret += &format!("    <pubDate>{}</pubDate>\n", crate::dates::rfc2822(entry.date));
+        ret += &format!("    <description>{}</description>\n", xml_escape(&entry.summary.clone().unwrap_or_else(|| excerpt(&entry.content))));
+        ret += &format!("    <content:encoded>{}</content:encoded>\n", xml_escape(&content));
+        ret += "  </item>\n";
+    }
+    ret += "</channel>\n</rss>\n";
+    ret
+}
+
+
+pub fn atom(settings : &crate::config::FeedSettings, title : &str, base_url : &str, self_url : &str, entries : &[Entry]) -> String {
+    let updated = entries.iter().map(|e| e.date).max().unwrap_or(0);
+    let mut ret = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    ret += &format!("  <title>{}</title>\n", xml_escape(title));
+    if !settings.description.is_empty() {
+        ret += &format!("  <subtitle>{}</subtitle>\n", xml_escape(&settings.description));
+    }
+    ret += &format!("  <link href=\"{}/\"/>\n", xml_escape(base_url.trim_end_matches('/')));
+    ret += &format!("  <link href=\"{}\" rel=\"self\"/>\n", xml_escape(self_url));
+    ret += &format!("  <id>{}</id>\n", xml_escape(self_url));
+    ret += &format!("  <updated>{}</updated>\n", crate::dates::rfc3339(updated));
+    ret += &format!("  <author>\n    <name>{}</name>\n  </author>\n", xml_escape(settings.author.as_deref().unwrap_or(title)));
+    for entry in entries {
+        ret += "  <entry>\n";
+        ret += &format!("    <title>{}</title>\n", xml_escape(&entry.title));
+        ret += &format!("    <link href=\"{}\"/>\n", xml_escape(&entry.url));
+        ret += &format!("    <id>{}</id>\n", xml_escape(&entry.url));
+        ret += &format!("    <updated>{}</updated>\n", crate::dates::rfc3339(entry.date));
+        if let Some(summary) = &entry.summary {
+            ret += &format!("    <summary>{}</summary>\n", xml_escape(summary));
+        }
+        ret += &format!("    <content type=\"html\">{}</content>\n", xml_escape(&absolutize(&entry.content, &entry.url, base_url)));
+        ret += "  </entry>\n";
+    }
+    ret += "</feed>\n";
+    ret
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excerpts_are_plain_text() {
+        assert_eq!(excerpt("<h1>Hello</h1><p>some <b>bold</b>, <a href=\"/x\">link</a>ed\n text</p><script>let x = '<p>no</p>';</script><style>p { }</style>"), "Hello some bold, linked text");
+        let long = format!("<p>{}</p>", "word ".repeat(80));
+        assert_eq!(excerpt(&long), format!("{}…", vec!["word"; EXCERPT_WORDS].join(" ")));
+    }
+
+    #[test]
+    fn links_are_made_absolute() {
+        let page = "https://example.com/blog/posts/hello/";
+        let base = "https://example.com/blog/";
+        let html = "<a href='/about/'>a</a> <img src=\"cat.png\" srcset=\"cat.png 1x, ../big.png 2x\"> <video poster=../p.jpg></video>\
+            <a href=\"#top\">t</a> <a href=\"https://other.org/x\">o</a> <a href=\"//cdn.example.com/x\">c</a> <a href=\"mailto:me@example.com\">m</a>\
+            <!-- <a href=\"x\"> --><script>let s = '<a href=\"x\">';</script><a href=\"./?a=1&amp;b=2\">q</a>";
+        assert_eq!(absolutize(html, page, base), "<a href='https://example.com/blog/about/'>a</a> \
+            <img src=\"https://example.com/blog/posts/hello/cat.png\" srcset=\"https://example.com/blog/posts/hello/cat.png 1x, https://example.com/blog/posts/big.png 2x\"> \
+            <video poster=https://example.com/blog/posts/p.jpg></video>\
+            <a href=\"https://example.com/blog/posts/hello/#top\">t</a> <a href=\"https://other.org/x\">o</a> <a href=\"//cdn.example.com/x\">c</a> <a href=\"mailto:me@example.com\">m</a>\
+            <!-- <a href=\"x\"> --><script>let s = '<a href=\"x\">';</script><a href=\"https://example.com/blog/posts/hello/?a=1&amp;b=2\">q</a>");
+        assert_eq!(join("https://example.com/a/page.html", base, ".."), "https://example.com/");
+        assert_eq!(join("https://example.com/a/page.html", base, "other.html#x"), "https://example.com/a/other.html#x");
+    }
+}
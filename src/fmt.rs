@@ -0,0 +1,279 @@
+/*  sitix fmt: reprint Rasta files in one canonical form. It only ever changes two things:
+    Tags. No space after the [, one space between a tag's name and the rest ([^ title   x -] becomes [^title x-]), and one space after
+    the # of a comment and the % of a short verbatim tag. What's inside assignments, defaults, comments and verbatim blocks is untouched,
+    and so is the whole body of a named assignment ([=nav-] ... [/]), tags and all, since that's a value and its whitespace is kept.
+    Indentation. Whatever's inside an extended tag ([=x-] ... [/]) that starts on a line of its own is indented four spaces past the line
+    the tag opened on, keeping its own relative indentation, and the [/] lines up with that line:
+[=-]
+    <ul>
+        [*item items-]
+            <li>[^item]</li>
+        [/]
+    </ul>
+[/]
+    Trailing whitespace goes, blank lines are emptied, and the file ends with one newline. Lines inside multi-line tags, verbatim blocks,
+    <pre>, <textarea>, <script> and <style> are left exactly as they are, since their whitespace shows (or can, in a string).
+
+    That means whitespace in text can change (which HTML mostly ignores), but nothing else: every file is checked after formatting, and
+    if anything but whitespace in text would render differently, it's left alone and reported instead.
+*/
+
+use crate::rasta::{self, LexerToken, SourceTag, SourceToken};
+
+
+const INDENT : usize = 4;
+const PREFORMATTED : &[&str] = &["pre", "textarea", "script", "style"]; // elements where whitespace shows, or might (a JavaScript template literal)
+
+
+fn print_tag(tag : &SourceTag) -> String {
+    let (content, trim_after) = tag.inside();
+    let (inner, extended) = match content.strip_suffix('-') {
+        Some(stripped) => (stripped.trim_end().to_string(), true),
+        None => (content, false)
+    };
+    let inner = match tag.control {
+        '#' | '/' | '%' => if inner.is_empty() { inner } else { format!(" {}", inner) },
+        _ => match inner.split_once(' ') { // the same split the parser makes: name, then everything else
+            Some((name, rest)) if !rest.trim().is_empty() => format!("{} {}", name, rest.trim()),
+            Some((name, _)) => name.to_string(),
+            None => inner
+        }
+    };
+    let mut ret = format!("[{}{}{}{}{}]", if tag.trim_before { "~" } else { "" }, tag.control, inner, if extended { "-" } else { "" }, if trim_after { "~" } else { "" });
+    if let Some(body) = &tag.body {
+        ret += body;
+        ret += tag.terminator().unwrap_or("[/%]");
+    }
+    ret
+}
+
+
+fn is_named(tag : &SourceTag) -> bool { // [=nav-] rather than the page's own [=-]
+    let (content, _) = tag.inside();
+    !content.trim_end_matches('-').trim().is_empty()
+}
+
+
+fn width(indent : &str) -> usize {
+    indent.chars().map(|c| if c == '\t' { INDENT } else { 1 }).sum()
+}
+
+
+struct Line {
+    block : usize, // the innermost extended tag open at the start of the line (0 is the top of the file)
+    closes : Option<usize>, // the block this line starts by closing
+    indent : usize, // the width of the indentation it had
+    frozen : bool, // starts inside something whose whitespace matters, so it's left as it is
+    keep_end : bool, // ends inside something like that, so its trailing whitespace stays
+    blank : bool
+}
+
+
+pub fn format(text : &str) -> Result<String, String> {
+    rasta::TreeNode::parse_str(text).map_err(|e| e.to_string())?;
+    // print every token canonically, noting where blocks open and close and which stretches have whitespace that matters
+    let mut out = String::new();
+    let mut events : Vec<(usize, bool)> = vec![]; // (offset, true for an opening tag and false for a [/])
+    let mut frozen : Vec<(usize, usize)> = vec![];
+    let mut preformatted : Option<(usize, &str)> = None;
+    let mut blocks : Vec<Option<usize>> = vec![]; // the open blocks, with where each one started if it's a named assignment
+    for token in rasta::source_tokens(text) {
+        match token {
+            SourceToken::Text (raw) => {
+                let lower = raw.to_ascii_lowercase();
+                let mut at = 0;
+                while at < lower.len() {
+                    match preformatted {
+                        Some((start, name)) => match lower[at..].find(&format!("</{}", name)) {
+                            Some(end) => {
+                                at += end + name.len() + 2;
+                                frozen.push((start, out.len() + at));
+                                preformatted = None;
+                            },
+                            None => break
+                        },
+                        None => match PREFORMATTED.iter().filter_map(|name| lower[at..].find(&format!("<{}", name)).map(|i| (i, *name)))
+                            .filter(|(i, name)| !lower[at + i + name.len() + 1..].starts_with(|c : char| c.is_ascii_alphanumeric())).min() {
+                            Some((i, name)) => {
+                                preformatted = Some((out.len() + at + i, name));
+                                at += i + name.len() + 1;
+                            },
+                            None => break
+                        }
+                    }
+                }
+                out += &raw;
+            },
+            SourceToken::Tag (tag) => {
+                let start = out.len();
+                out += &if blocks.iter().any(Option::is_some) { tag.text.clone() } else { print_tag(&tag) };
+                frozen.push((start, out.len()));
+                if tag.control == '/' {
+                    events.push((start, false));
+                    if let Some(Some(opened)) = blocks.pop() {
+                        frozen.push((opened, out.len()));
+                    }
+                }
+                else if tag.opens_block() {
+                    events.push((start, true));
+                    blocks.push(if tag.control == '=' && is_named(&tag) { Some(start) } else { None });
+                }
+            }
+        }
+    }
+    if let Some((start, _)) = preformatted {
+        frozen.push((start, out.len()));
+    }
+    let inside = |offset : usize| frozen.iter().any(|(start, end)| *start < offset && offset < *end);
+    // work out which block every line is in
+    let starts : Vec<usize> = std::iter::once(0).chain(out.match_indices('\n').map(|(i, _)| i + 1)).collect();
+    let mut lines = vec![];
+    let mut opened_on = vec![0]; // block -> the line it opened on
+    let mut stack = vec![0];
+    let mut next = 0;
+    for (i, &start) in starts.iter().enumerate() {
+        let end = starts.get(i + 1).map(|next| next - 1).unwrap_or(out.len());
+        let line = &out[start..end];
+        let content = start + line.len() - line.trim_start().len();
+        while next < events.len() && events[next].0 < content {
+            let (offset, opens) = events[next];
+            if opens {
+                stack.push(opened_on.len());
+                opened_on.push(starts.partition_point(|&s| s <= offset) - 1);
+            }
+            else if stack.len() > 1 {
+                stack.pop();
+            }
+            next += 1;
+        }
+        let mut closes = None;
+        if next < events.len() && events[next] == (content, false) && stack.len() > 1 {
+            closes = stack.pop();
+            next += 1;
+        }
+        lines.push(Line {
+            block : *stack.last().unwrap(),
+            closes,
+            indent : width(&line[..line.len() - line.trim_start().len()]),
+            frozen : inside(start),
+            keep_end : inside(end),
+            blank : line.trim().is_empty()
+        });
+    }
+    // each block's lines keep their indentation relative to its least indented line
+    let mut base = vec![usize::MAX; opened_on.len()];
+    for line in &lines {
+        if !line.frozen && !line.blank && line.closes.is_none() {
+            base[line.block] = base[line.block].min(line.indent);
+        }
+    }
+    let mut indents : Vec<usize> = vec![];
+    let mut ret = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        let start = starts[i];
+        let end = starts.get(i + 1).map(|next| next - 1).unwrap_or(out.len());
+        let text = &out[start..end];
+        let indent = if line.frozen {
+            line.indent
+        }
+        else if let Some(block) = line.closes {
+            indents[opened_on[block]]
+        }
+        else if line.block == 0 {
+            line.indent - base[0].min(line.indent)
+        }
+        else {
+            indents[opened_on[line.block]] + INDENT + line.indent - base[line.block].min(line.indent)
+        };
+        indents.push(indent);
+        if line.frozen {
+            ret += text;
+        }
+        else if !line.blank {
+            ret += &" ".repeat(indent);
+            ret += if line.keep_end { text.trim_start() } else { text.trim() };
+        }
+        else if line.keep_end {
+            ret += text;
+        }
+        ret.push('\n');
+    }
+    ret.truncate(ret.trim_end_matches('\n').len());
+    ret.push('\n');
+    check(text, &ret)?;
+    Ok(ret)
+}
+
+
+fn check(before : &str, after : &str) -> Result<(), String> { // make sure formatting didn't change anything that matters
+    let normalize = |text : &str| -> Vec<String> {
+        let mut blocks : Vec<bool> = vec![]; // the open blocks, and whether each one is a named assignment, where whitespace is part of the value
+        rasta::lexer_str(text).unwrap_or_default().into_iter().filter_map(|token| {
+            let exact = blocks.contains(&true);
+            let opens = matches!(token, LexerToken::ExtTag (..));
+            match token {
+                LexerToken::PlainText (text) if exact => Some(format!("value {:?}", text)),
+                LexerToken::PlainText (text) if text.trim().is_empty() => None,
+                LexerToken::PlainText (text) => Some(format!("text {}", text.split_whitespace().collect::<Vec<_>>().join(" "))),
+                LexerToken::SimpleTag (control, content) | LexerToken::ExtTag (control, content) => { // split the way the parser does
+                    let (name, rest) = content.split_once(' ').unwrap_or((&content, ""));
+                    if opens {
+                        blocks.push(control == '=' && !name.trim().is_empty());
+                    }
+                    Some(format!("{} {:?} {:?}", control, name, rest.trim()))
+                },
+                LexerToken::ClosingTag => {
+                    blocks.pop();
+                    Some("/".to_string())
+                }
+            }
+        }).collect()
+    };
+    if normalize(before) != normalize(after) || rasta::TreeNode::parse_str(after).is_err() {
+        return Err("formatting it would change what it renders, so it's been left alone".to_string());
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indents_blocks() {
+        assert_eq!(format("[ =-]\n<ul>\n[*item  items -]\n  <li>[^item]</li>  \n[/]\n</ul>\n[/]").unwrap(), "[=-]\n    <ul>\n    [*item items-]\n        <li>[^item]</li>\n    [/]\n    </ul>\n[/]\n");
+    }
+
+    #[test]
+    fn formatting_twice_changes_nothing() {
+        let once = format("[=-]\n[=title  Hi]\n  [=list-]\n[=a 1]\n     [/]\n<p>\n  text\n</p>\n[/]\n\n\n").unwrap();
+        assert_eq!(format(&once).unwrap(), once);
+    }
+
+    #[test]
+    fn assignment_values_are_left_alone() {
+        let render = |page : &str| { // what the page renders (give or take whitespace in text, which fmt may change), and nav exactly
+            let scope = rasta::Scope::top().wrap();
+            let rendered = rasta::TreeNode::parse_str(page).unwrap().render(scope.clone());
+            let nav = scope.borrow().get("nav".to_string());
+            (rendered.split_whitespace().collect::<Vec<_>>().join(" "), nav)
+        };
+        let page = "[ =-]\n[ =nav  -]\n  <a>\n    [ ^title -]x[/]  \n\t</a>\n[/]\n<nav>[^nav]</nav>\n[/]\n";
+        let formatted = format(page).unwrap();
+        assert!(formatted.contains("\n  <a>\n    [ ^title -]x[/]  \n\t</a>\n[/]"));
+        assert_eq!(render(&formatted).1.as_deref(), Some("\n  <a>\n    x  \n\t</a>\n"));
+        assert_eq!(render(&formatted), render(page));
+        let header = "[=-]\n<p>\n[=x-]\n  a\n[/]\n</p>\n[/]\n";
+        assert_eq!(format(header).unwrap(), "[=-]\n    <p>\n    [=x-]\n  a\n[/]\n    </p>\n[/]\n");
+    }
+
+    #[test]
+    fn preformatted_and_scripts_are_left_alone() {
+        let text = "[=-]\n<pre>\n  a\n    b\n</pre>\n<script>\nlet s = `a\n    b`;  \n</script>\n<style>\n  p { }\n</style>\n[/]\n";
+        let formatted = format(text).unwrap();
+        assert!(formatted.contains("<pre>\n  a\n    b\n</pre>"));
+        assert!(formatted.contains("let s = `a\n    b`;  \n</script>"));
+        assert!(formatted.contains("<style>\n  p { }\n</style>"));
+    }
+}
@@ -0,0 +1,107 @@
+/*  Build-time syntax highlighting, so no client-side JavaScript is needed:
+[@highlight rust-]
+fn main() {
+    println!("hello");
+}
+[/]
+    becomes a <pre class="highlight"><code class="language-rust"> full of <span class="hl-..."> elements. The body isn't lexed as
+    Rasta: everything up to the first [/] is highlighted exactly as written, so brackets and backslashes need no escaping.
+    The grammars are embedded in sitix (everything Sublime Text ships with), and the language can be a name or file extension.
+
+    The colors live in a generated stylesheet, written to the output directory if anything was highlighted. In sitix.toml:
+
+[highlight]
+theme = "InspiredGitHub"       # or base16-ocean.dark, Solarized (light), ...
+stylesheet = "highlight.css"   # where to write it, relative to the output directory
+*/
+
+use syntect::html::{ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+
+const CLASS_STYLE : ClassStyle = ClassStyle::SpacedPrefixed { prefix : "hl-" };
+
+
+pub struct Highlighter {
+    syntaxes : SyntaxSet,
+    pub used : bool // whether anything has been highlighted yet, and so whether the stylesheet is needed
+}
+
+
+impl Default for Highlighter {
+    fn default() -> Self {
+        Self {
+            syntaxes : SyntaxSet::load_defaults_newlines(),
+            used : false
+        }
+    }
+}
+
+
+impl Highlighter {
+    pub fn highlight(&mut self, language : &str, code : &str) -> Result<String, Box<dyn std::error::Error + 'static>> {
+        let language = language.trim();
+        let syntax = self.syntaxes.find_syntax_by_token(language).unwrap_or_else(|| self.syntaxes.find_syntax_plain_text());
+        let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, &self.syntaxes, CLASS_STYLE);
+        for line in LinesWithEndings::from(trim_code(code)) {
+            generator.parse_html_for_line_which_includes_newline(line)?;
+        }
+        self.used = true;
+        Ok(format!("<pre class=\"highlight\"><code class=\"language-{}\">{}</code></pre>", escape_attribute(language), generator.finalize()))
+    }
+}
+
+
+pub fn stylesheet(theme : &str) -> Result<String, Box<dyn std::error::Error + 'static>> {
+    let themes = syntect::highlighting::ThemeSet::load_defaults();
+    let theme = themes.themes.get(theme).ok_or_else(|| format!("unknown highlighting theme {:?} (try one of: {})", theme, themes.themes.keys().cloned().collect::<Vec<_>>().join(", ")))?;
+    Ok(syntect::html::css_for_theme_with_class_style(theme, CLASS_STYLE)?)
+}
+
+
+fn trim_code(code : &str) -> &str { // drop the blank lines around the code, which come from putting it on its own lines in the tag
+    let code = code.trim_end();
+    match code.find(|c : char| !c.is_whitespace()) {
+        Some(first) => {
+            let line_start = code[..first].rfind('\n').map(|n| n + 1).unwrap_or(0); // keep the first line's indentation
+            &code[line_start..]
+        },
+        None => ""
+    }
+}
+
+
+fn escape_attribute(text : &str) -> String {
+    text.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_is_tokenized() {
+        let mut highlighter = Highlighter::default();
+        assert!(!highlighter.used);
+        let html = highlighter.highlight(" rust ", "\n\n    fn main() {}\n\n").unwrap();
+        assert!(highlighter.used);
+        assert!(html.starts_with("<pre class=\"highlight\"><code class=\"language-rust\">"));
+        assert!(html.contains(">fn</span>"), "{}", html);
+        assert!(html.contains(">main</span>"), "{}", html);
+        assert!(html.contains("    <span"), "{}", html); // the first line keeps its indentation, but not the blank lines before it
+        assert!(!html.contains("\n\n"));
+        assert_eq!(highlighter.highlight("rs", "fn main() {}").unwrap(), highlighter.highlight("rust", "fn main() {}").unwrap().replace("language-rust", "language-rs"));
+    }
+
+    #[test]
+    fn html_is_escaped() {
+        let mut highlighter = Highlighter::default();
+        let html = highlighter.highlight("rust", "if a < b && c > d { \"<b>\" }").unwrap();
+        assert!(html.contains(">&lt;<") && html.contains(">&amp;<") && html.contains(">&gt;<") && html.contains("&lt;b&gt;"), "{}", html);
+        assert!(!html.contains("<b>"));
+        let plain = highlighter.highlight("no such language\"><script>", "<script>&</script>").unwrap();
+        assert_eq!(plain, "<pre class=\"highlight\"><code class=\"language-no such language&quot;&gt;&lt;script&gt;\"><span class=\"hl-text hl-plain\">&lt;script&gt;&amp;&lt;/script&gt;</span></code></pre>");
+    }
+}
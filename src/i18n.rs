@@ -0,0 +1,196 @@
+/*  Multilingual sites. Turn it on by listing the languages in sitix.toml:
+
+[i18n]
+default_language = "en"
+languages = ["en", "fr", "de"]
+strings = "_i18n"        # translation strings, one file per language: _i18n/fr.toml
+prefix_default = false   # whether default-language pages go under en/ too
+
+    A page's language comes from its path. Either keep a tree per language (fr/about.html), or put the language before the extension
+    (about.fr.html). Anything else is in the default language. Pages in other languages are written under a directory named for their
+    language, so about.fr.html and fr/about.html both end up at fr/about.html, and en/about.html ends up at about.html.
+    Pages with the same path once the language is taken out are translations of each other.
+
+    Every page gets content.lang, and content.translations, which has lang and url for each translation of it (itself included):
+<html lang="[^content.lang]">
+[*t content.translations-]<link rel="alternate" hreflang="[^t.lang]" href="[^t.url]">[/]
+    The URLs are absolute if base_url is set. Translation strings are looked up with [@t nav.home], which finds home in the [nav]
+    table of the current page's language file, falling back on the default language and then on the key itself.
+*/
+
+use crate::config::I18nSettings;
+use crate::rasta::Scope;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+
+pub fn split(settings : &I18nSettings, relative : &Path, rendered : bool) -> (String, PathBuf) {
+    // relative path in the source directory -> (language, the same path with the language taken out). Only rendered pages can use a suffix;
+    // an asset like jquery.de.js is just named that.
+    if let Some(first) = relative.components().next() {
+        let first = first.as_os_str().to_string_lossy();
+        if settings.languages.iter().any(|language| *language == first) {
+            return (first.to_string(), relative.components().skip(1).collect());
+        }
+    }
+    if rendered {
+        if let (Some(stem), Some(extension)) = (relative.file_stem(), relative.extension()) {
+            let stem = Path::new(stem);
+            if let Some(language) = stem.extension().map(|l| l.to_string_lossy().to_string()) {
+                if settings.languages.contains(&language) {
+                    let mut ret = relative.with_file_name(stem.file_stem().unwrap());
+                    ret.set_extension(extension);
+                    return (language, ret);
+                }
+            }
+        }
+    }
+    (settings.default_language.clone(), relative.to_path_buf())
+}
+
+
+pub fn prefix(settings : &I18nSettings, language : &str, path : PathBuf) -> PathBuf { // where a page in this language goes in the output directory
+    if language == settings.default_language && !settings.prefix_default {
+        path
+    }
+    else {
+        Path::new(language).join(path)
+    }
+}
+
+
+#[derive(Default)]
+pub struct Strings {
+    default_language : String,
+    tables : BTreeMap<String, toml::Table> // language -> the contents of its strings file
+}
+
+
+impl Strings {
+    pub fn load(settings : &I18nSettings) -> Result<Self, Box<dyn std::error::Error + 'static>> {
+        let mut ret = Self {
+            default_language : settings.default_language.clone(),
+            tables : BTreeMap::new()
+        };
+        let mut languages = settings.languages.clone();
+        if !languages.contains(&settings.default_language) {
+            languages.push(settings.default_language.clone());
+        }
+        for language in languages {
+            let path = settings.strings.join(format!("{}.toml", language));
+            if path.exists() {
+                let table = toml::from_str(&std::fs::read_to_string(&path)?).map_err(|e| format!("{}: {}", path.display(), e))?;
+                ret.tables.insert(language, table);
+            }
+        }
+        Ok(ret)
+    }
+
+    pub fn translate(&self, language : &str, key : &str) -> Option<String> {
+        self.lookup(language, key).or_else(|| self.lookup(&self.default_language, key))
+    }
+
+    fn lookup(&self, language : &str, key : &str) -> Option<String> {
+        let mut table = self.tables.get(language)?;
+        let mut parts = key.split('.').peekable();
+        while let Some(part) = parts.next() {
+            match table.get(part)? {
+                toml::Value::Table (inner) if parts.peek().is_some() => table = inner,
+                toml::Value::String (s) if parts.peek().is_none() => return Some(s.clone()),
+                toml::Value::Table (_) => return None, // a group of strings, not one
+                other if parts.peek().is_none() => return Some(other.to_string()),
+                _ => return None
+            }
+        }
+        None
+    }
+}
+
+
+#[derive(Default)]
+pub struct Translations {
+    groups : BTreeMap<PathBuf, Vec<(String, String)>> // path with the language taken out -> (language, url) for every version of it
+}
+
+
+impl Translations {
+    pub fn add(&mut self, key : PathBuf, language : String, url : String) {
+        let group = self.groups.entry(key).or_default();
+        group.push((language, url));
+        group.sort();
+    }
+
+    pub fn fill_scope(&self, key : &Path, scope : Rc<RefCell<Scope>>) { // scope should be the content scope
+        for (language, url) in self.groups.get(key).into_iter().flatten() {
+            Scope::set_w(scope.clone(), &format!("translations.{}", language), url.clone());
+            Scope::set_w(scope.clone(), &format!("translations.{}.lang", language), language.clone());
+            Scope::set_w(scope.clone(), &format!("translations.{}.url", language), url.clone());
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> I18nSettings {
+        I18nSettings {
+            languages : vec!["en".to_string(), "fr".to_string()],
+            ..I18nSettings::default()
+        }
+    }
+
+    #[test]
+    fn languages_come_from_paths() {
+        let settings = settings();
+        assert_eq!(split(&settings, Path::new("fr/about.html"), true), ("fr".to_string(), PathBuf::from("about.html")));
+        assert_eq!(split(&settings, Path::new("blog/post.fr.md"), true), ("fr".to_string(), PathBuf::from("blog/post.md")));
+        assert_eq!(split(&settings, Path::new("about.html"), true), ("en".to_string(), PathBuf::from("about.html")));
+        assert_eq!(split(&settings, Path::new("js/jquery.fr.js"), false), ("en".to_string(), PathBuf::from("js/jquery.fr.js")));
+        assert_eq!(split(&settings, Path::new("js/jquery.de.html"), true), ("en".to_string(), PathBuf::from("js/jquery.de.html")));
+    }
+
+    #[test]
+    fn other_languages_get_a_directory() {
+        let mut settings = settings();
+        let (language, path) = split(&settings, Path::new("en/about.html"), true);
+        assert_eq!(prefix(&settings, &language, path), PathBuf::from("about.html"));
+        let (language, path) = split(&settings, Path::new("about.fr.html"), true);
+        assert_eq!(prefix(&settings, &language, path), PathBuf::from("fr/about.html"));
+        settings.prefix_default = true;
+        assert_eq!(prefix(&settings, "en", PathBuf::from("about.html")), PathBuf::from("en/about.html"));
+    }
+
+    #[test]
+    fn strings_fall_back_on_the_default_language() {
+        let strings = Strings {
+            default_language : "en".to_string(),
+            tables : BTreeMap::from([
+                ("en".to_string(), toml::from_str("[nav]\nhome = \"Home\"\nabout = \"About\"\ncount = 3").unwrap()),
+                ("fr".to_string(), toml::from_str("[nav]\nhome = \"Accueil\"").unwrap())
+            ])
+        };
+        assert_eq!(strings.translate("fr", "nav.home").as_deref(), Some("Accueil"));
+        assert_eq!(strings.translate("fr", "nav.about").as_deref(), Some("About"));
+        assert_eq!(strings.translate("de", "nav.home").as_deref(), Some("Home"));
+        assert_eq!(strings.translate("en", "nav.count").as_deref(), Some("3"));
+        assert_eq!(strings.translate("fr", "nav"), None);
+        assert_eq!(strings.translate("fr", "nav.home.more"), None);
+        assert_eq!(strings.translate("fr", "missing"), None);
+    }
+
+    #[test]
+    fn translations_list_every_version() {
+        let mut translations = Translations::default();
+        translations.add(PathBuf::from("about.html"), "fr".to_string(), "/fr/about.html".to_string());
+        translations.add(PathBuf::from("about.html"), "en".to_string(), "/about.html".to_string());
+        translations.add(PathBuf::from("other.html"), "en".to_string(), "/other.html".to_string());
+        let content = Scope::top().wrap();
+        translations.fill_scope(Path::new("about.html"), content.clone());
+        let page = crate::rasta::TreeNode::parse_str("[=-][*t translations-]<link hreflang=\"[^t.lang]\" href=\"[^t.url]\">[/][/]").unwrap();
+        assert_eq!(page.render(content), "<link hreflang=\"en\" href=\"/about.html\"><link hreflang=\"fr\" href=\"/fr/about.html\">");
+    }
+}
@@ -0,0 +1,272 @@
+/*  Ignore rules: decide which files in the source tree sitix should leave alone.
+
+    By default, anything whose name starts with a dot (.git, .DS_Store, .sitixignore, editor swap files like .index.html.swp) and any
+    directory whose name starts with an underscore (_templates, _drafts) is skipped.
+    On top of that, a .sitixignore file in the project root can list more patterns, one per line, gitignore-style:
+
+# comments start with a hash
+node_modules/
+README.md
+*.psd
+/drafts/**/*.html
+!.well-known
+
+    A pattern without a slash matches the name of a file or directory at any depth; a pattern with a slash in it is matched against the
+    whole path relative to the project root. A trailing slash means "only directories". * matches anything except a slash, ? matches one
+    character, ** matches any number of directories, and [abc] / [!a-z] are character classes. A backslash escapes the next character.
+    Patterns starting with ! are includes: they un-ignore anything they match, including things skipped by the defaults.
+    The last pattern that matches wins, just like git.
+*/
+
+
+pub struct IgnoreRules {
+    root : std::path::PathBuf,
+    rules : Vec<Rule>
+}
+
+
+struct Rule {
+    pattern : Vec<char>,
+    anchored : bool, // match against the whole relative path instead of just the name
+    dir_only : bool,
+    include : bool
+}
+
+
+impl Rule {
+    fn new(line : &str, include : bool) -> Option<Self> {
+        let mut line = line;
+        let dir_only = line.ends_with('/');
+        if dir_only {
+            line = &line[..line.len() - 1];
+        }
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        if line.is_empty() {
+            return None;
+        }
+        Some(Self {
+            pattern : line.chars().collect(),
+            anchored,
+            dir_only,
+            include
+        })
+    }
+
+    fn matches(&self, relative : &str, name : &str, is_dir : bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let text : Vec<char> = if self.anchored { relative } else { name }.chars().collect();
+        glob_match(&self.pattern, &text)
+    }
+}
+
+
+impl IgnoreRules {
+    pub fn new(root : std::path::PathBuf) -> Self { // just the default rules
+        Self {
+            root,
+            rules : vec![]
+        }
+    }
+
+    pub fn load(root : std::path::PathBuf) -> Result<Self, std::io::Error> { // default rules plus whatever is in root/.sitixignore, if it exists
+        let mut ret = Self::new(root);
+        let mut path = ret.root.clone();
+        path.push(".sitixignore");
+        if path.exists() {
+            for line in std::fs::read_to_string(path)?.lines() {
+                ret.add_line(line);
+            }
+        }
+        Ok(ret)
+    }
+
+    pub fn add_line(&mut self, line : &str) { // add a single .sitixignore-formatted line
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return;
+        }
+        if let Some(include) = line.strip_prefix('!') {
+            self.add_include(include);
+        }
+        else {
+            self.add_ignore(line);
+        }
+    }
+
+    pub fn add_ignore(&mut self, pattern : &str) {
+        if let Some(rule) = Rule::new(pattern.trim(), false) {
+            self.rules.push(rule);
+        }
+    }
+
+    pub fn add_include(&mut self, pattern : &str) {
+        if let Some(rule) = Rule::new(pattern.trim(), true) {
+            self.rules.push(rule);
+        }
+    }
+
+    pub fn is_ignored(&self, path : &std::path::Path, is_dir : bool) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        let relative = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => {
+                return false;
+            }
+        };
+        let mut ignored = name.starts_with('.') || (is_dir && name.starts_with('_'));
+        for rule in &self.rules {
+            if rule.matches(&relative, &name, is_dir) {
+                ignored = !rule.include;
+            }
+        }
+        ignored
+    }
+}
+
+
+fn glob_match(pattern : &[char], text : &[char]) -> bool { // simple recursive backtracking matcher. Paths are short, so this is plenty fast.
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            let rest = &pattern[2..];
+            if let Some(rest) = rest.strip_prefix(&['/']) { // **/ - zero or more whole directories
+                if glob_match(rest, text) {
+                    return true;
+                }
+                (0..text.len()).any(|i| text[i] == '/' && glob_match(rest, &text[i + 1..]))
+            }
+            else { // ** anywhere else matches anything, slashes included
+                (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+            }
+        },
+        Some('*') => {
+            for i in 0..=text.len() {
+                if glob_match(&pattern[1..], &text[i..]) {
+                    return true;
+                }
+                if i < text.len() && text[i] == '/' {
+                    break;
+                }
+            }
+            false
+        },
+        Some('?') => {
+            !text.is_empty() && text[0] != '/' && glob_match(&pattern[1..], &text[1..])
+        },
+        Some('[') => {
+            if text.is_empty() || text[0] == '/' {
+                return false;
+            }
+            match match_class(&pattern[1..], text[0]) {
+                Some((true, len)) => glob_match(&pattern[len + 1..], &text[1..]),
+                Some((false, _)) => false,
+                None => !text.is_empty() && text[0] == '[' && glob_match(&pattern[1..], &text[1..]) // unclosed class: literal [
+            }
+        },
+        Some('\\') if pattern.len() > 1 => {
+            !text.is_empty() && text[0] == pattern[1] && glob_match(&pattern[2..], &text[1..])
+        },
+        Some(c) => {
+            !text.is_empty() && text[0] == *c && glob_match(&pattern[1..], &text[1..])
+        }
+    }
+}
+
+
+fn match_class(class : &[char], c : char) -> Option<(bool, usize)> { // class starts right after the [. Returns whether c matched and how many chars of the class (including the ]) were used.
+    let mut i = 0;
+    let negated = matches!(class.first(), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    while i < class.len() {
+        if class[i] == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        if i + 2 < class.len() && class[i + 1] == '-' && class[i + 2] != ']' {
+            if class[i] <= c && c <= class[i + 2] {
+                matched = true;
+            }
+            i += 3;
+        }
+        else {
+            if class[i] == c {
+                matched = true;
+            }
+            i += 1;
+        }
+    }
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn glob(pattern : &str, text : &str) -> bool {
+        glob_match(&pattern.chars().collect::<Vec<_>>(), &text.chars().collect::<Vec<_>>())
+    }
+
+    fn rules(lines : &str) -> IgnoreRules {
+        let mut ret = IgnoreRules::new("/site".into());
+        for line in lines.lines() {
+            ret.add_line(line);
+        }
+        ret
+    }
+
+    #[test]
+    fn stars() {
+        assert!(glob("*.psd", "cat.psd"));
+        assert!(!glob("*.psd", "art/cat.psd")); // * stops at slashes
+        assert!(glob("drafts/**/*.html", "drafts/a.html"));
+        assert!(glob("drafts/**/*.html", "drafts/2024/05/a.html"));
+        assert!(!glob("drafts/**/*.html", "drafts/a.css"));
+        assert!(glob("a/**", "a/b/c"));
+        assert!(glob("**/cache", "x/y/cache"));
+        assert!(glob("file?.txt", "file1.txt") && !glob("file?.txt", "file/.txt"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(glob("[abc].txt", "b.txt") && !glob("[abc].txt", "d.txt"));
+        assert!(glob("v[0-9]", "v7") && !glob("v[0-9]", "vx"));
+        assert!(glob("[!a-z]x", "9x") && !glob("[!a-z]x", "qx"));
+        assert!(glob("[]]", "]")); // a ] first is part of the class
+        assert!(glob("[oops", "[oops")); // unclosed: a literal [
+        assert!(glob("\\*", "*") && !glob("\\*", "a"));
+        assert_eq!(match_class(&"a-c]rest".chars().collect::<Vec<_>>(), 'b'), Some((true, 4)));
+        assert_eq!(match_class(&"abc".chars().collect::<Vec<_>>(), 'a'), None);
+    }
+
+    #[test]
+    fn anchoring() {
+        let rules = rules("README.md\n/drafts\nbuild/\n");
+        assert!(rules.is_ignored(Path::new("/site/README.md"), false));
+        assert!(rules.is_ignored(Path::new("/site/docs/README.md"), false)); // no slash: any depth
+        assert!(rules.is_ignored(Path::new("/site/drafts"), true));
+        assert!(!rules.is_ignored(Path::new("/site/blog/drafts"), true)); // leading slash: only at the root
+        assert!(rules.is_ignored(Path::new("/site/a/build"), true));
+        assert!(!rules.is_ignored(Path::new("/site/a/build"), false)); // trailing slash: only directories
+    }
+
+    #[test]
+    fn includes_win_when_they_come_last() {
+        let rules = rules("*.html\n!keep.html\n# a comment\n!.well-known\n!_data\n_data/secret.html\n");
+        assert!(rules.is_ignored(Path::new("/site/a.html"), false));
+        assert!(!rules.is_ignored(Path::new("/site/keep.html"), false));
+        assert!(!rules.is_ignored(Path::new("/site/.well-known"), true)); // the defaults can be overridden too
+        assert!(rules.is_ignored(Path::new("/site/.git"), true));
+        assert!(!rules.is_ignored(Path::new("/site/_data"), true));
+        assert!(rules.is_ignored(Path::new("/site/_data/secret.html"), false));
+    }
+}
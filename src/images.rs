@@ -0,0 +1,201 @@
+/*  Responsive images. In a template or page,
+<img [@image /photos/cat.jpg] alt="A cat" sizes="(max-width: 600px) 100vw, 50vw">
+    renders as
+<img src="/photos/cat-1600.jpg" srcset="/photos/cat-480.jpg 480w, /photos/cat-960.jpg 960w, /photos/cat-1600.jpg 1600w" width="1600" height="1200" alt="A cat" sizes="...">
+    with every size resized, converted and written to the output directory. Images are never scaled up: widths bigger than the original
+    are dropped, and the original width is always included. Settings live in sitix.toml:
+
+[images]
+widths = [480, 960, 1600]
+format = "webp"        # webp, jpeg, png (for transparency), or original to keep the source format
+cache = ".sitix-cache" # resized images are kept here between builds, keyed by a hash of the source file
+
+    The image crate can only write lossless WebP, which for photos is often bigger than the JPEG it came from. So for a JPEG or PNG
+    source, each size is encoded both ways and whichever comes out smaller is kept; a srcset can mix the two.
+    Paths are relative to the source directory, like asset_url; a leading / is kept in the generated URLs.
+*/
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+
+pub struct Images {
+    settings : crate::config::ImageSettings,
+    source : PathBuf,
+    generated : BTreeMap<PathBuf, PathBuf>, // output path (relative to the output directory) -> cached file to copy there
+    done : HashMap<String, String> // attributes we've already worked out this build, so pages sharing an image don't redo it
+}
+
+
+impl Images {
+    pub fn new(config : &crate::config::Config) -> Self {
+        Self {
+            settings : config.images.clone(),
+            source : config.source.clone(),
+            generated : BTreeMap::new(),
+            done : HashMap::new()
+        }
+    }
+
+    pub fn generated(&self) -> impl Iterator<Item = (&PathBuf, &PathBuf)> {
+        self.generated.iter()
+    }
+
+    pub fn attributes(&mut self, path : &str) -> Result<String, Box<dyn std::error::Error + 'static>> { // src, srcset, width and height for an image
+        let path = path.trim();
+        if let Some(done) = self.done.get(path) {
+            return Ok(done.clone());
+        }
+        let (slash, bare) = match path.strip_prefix('/') {
+            Some(bare) => ("/", bare),
+            None => ("", path)
+        };
+        let source = self.source.join(bare);
+        let data = std::fs::read(&source).map_err(|e| format!("{}: {}", source.display(), e))?;
+        let (width, height) = image::ImageReader::new(std::io::Cursor::new(&data)).with_guessed_format()?.into_dimensions()?;
+        let formats = self.formats(&source)?;
+        let hash = crate::assets::content_hash(&data);
+        let mut decoded : Option<image::DynamicImage> = None;
+        let mut srcset = vec![];
+        let mut largest = (String::new(), width, height);
+        for w in widths(&self.settings.widths, width) {
+            let h = ((height as u64 * w as u64 + width as u64 / 2) / width as u64).max(1) as u32;
+            let cache_path = |extension : &str| self.settings.cache.join(format!("{:016x}-{}.{}", hash, w, extension));
+            let (cached, extension) = match formats.iter().map(|(_, extension)| (cache_path(extension), extension.clone())).find(|(cached, _)| cached.exists()) {
+                Some(found) => found,
+                None => {
+                    if decoded.is_none() {
+                        decoded = Some(image::load_from_memory(&data)?);
+                    }
+                    let resized = decoded.as_ref().unwrap().resize_exact(w, h, image::imageops::FilterType::Lanczos3);
+                    let mut smallest : Option<(Vec<u8>, &str)> = None;
+                    for (format, extension) in &formats {
+                        let encoded = encode(&resized, *format)?;
+                        if smallest.as_ref().is_none_or(|(best, _)| encoded.len() < best.len()) {
+                            smallest = Some((encoded, extension));
+                        }
+                    }
+                    let (encoded, extension) = smallest.unwrap();
+                    std::fs::create_dir_all(&self.settings.cache)?;
+                    let cached = cache_path(extension);
+                    let partial = cached.with_extension(format!("{}.partial", extension)); // so an interrupted build can't leave half an image in the cache
+                    std::fs::write(&partial, encoded)?;
+                    std::fs::rename(&partial, &cached)?;
+                    (cached, extension.to_string())
+                }
+            };
+            let output = variant_path(Path::new(bare), w, &extension);
+            let url = format!("{}{}", slash, output.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/"));
+            srcset.push(format!("{} {}w", url, w));
+            self.generated.insert(output, cached);
+            largest = (url, w, h);
+        }
+        let ret = format!("src=\"{}\" srcset=\"{}\" width=\"{}\" height=\"{}\"", largest.0, srcset.join(", "), largest.1, largest.2);
+        self.done.insert(path.to_string(), ret.clone());
+        Ok(ret)
+    }
+
+    fn formats(&self, source : &Path) -> Result<Vec<(image::ImageFormat, String)>, Box<dyn std::error::Error + 'static>> { // what to try encoding each size as
+        let original = || -> Result<(image::ImageFormat, String), Box<dyn std::error::Error + 'static>> {
+            Ok((image::ImageFormat::from_path(source)?, source.extension().unwrap().to_string_lossy().to_string()))
+        };
+        Ok(match self.settings.format.as_str() {
+            "webp" => {
+                let mut ret = vec![(image::ImageFormat::WebP, "webp".to_string())];
+                match original() { // lossless WebP can lose to the source's own format
+                    Ok(fallback) if matches!(fallback.0, image::ImageFormat::Jpeg | image::ImageFormat::Png) => ret.push(fallback),
+                    _ => {}
+                }
+                ret
+            },
+            "png" => vec![(image::ImageFormat::Png, "png".to_string())],
+            "jpeg" | "jpg" => vec![(image::ImageFormat::Jpeg, "jpg".to_string())],
+            "original" => vec![original()?],
+            other => {
+                return Err(format!("unknown image format {:?}", other).into());
+            }
+        })
+    }
+}
+
+
+fn widths(configured : &[u32], width : u32) -> Vec<u32> { // the configured widths smaller than the original, plus the original
+    let mut ret : Vec<u32> = configured.iter().copied().filter(|w| *w > 0 && *w < width).collect();
+    ret.push(width);
+    ret.sort();
+    ret.dedup();
+    ret
+}
+
+
+fn encode(image : &image::DynamicImage, format : image::ImageFormat) -> Result<Vec<u8>, image::ImageError> {
+    let image = match format { // not every encoder takes every pixel layout
+        image::ImageFormat::Jpeg => image::DynamicImage::ImageRgb8(image.to_rgb8()),
+        image::ImageFormat::WebP => image::DynamicImage::ImageRgba8(image.to_rgba8()),
+        _ => image.clone()
+    };
+    let mut ret = std::io::Cursor::new(vec![]);
+    image.write_to(&mut ret, format)?;
+    Ok(ret.into_inner())
+}
+
+
+fn variant_path(path : &Path, width : u32, extension : &str) -> PathBuf { // photos/cat.png -> photos/cat-480.jpg
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    path.with_file_name(format!("{}-{}.{}", stem, width, extension))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variants_are_named_after_their_width() {
+        assert_eq!(variant_path(Path::new("photos/cat.png"), 480, "webp"), PathBuf::from("photos/cat-480.webp"));
+        assert_eq!(variant_path(Path::new("cat.tar.jpg"), 960, "jpg"), PathBuf::from("cat.tar-960.jpg"));
+    }
+
+    #[test]
+    fn images_are_never_scaled_up() {
+        assert_eq!(widths(&[480, 960, 1600], 2000), [480, 960, 1600, 2000]);
+        assert_eq!(widths(&[480, 960, 1600], 960), [480, 960]);
+        assert_eq!(widths(&[480, 960, 1600], 300), [300]);
+        assert_eq!(widths(&[0, 100], 200), [100, 200]);
+    }
+
+    #[test]
+    fn resized_images_are_cached_by_hash() {
+        let directory = std::env::temp_dir().join(format!("sitix-images-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(directory.join("photos")).unwrap();
+        image::RgbImage::from_fn(64, 32, |x, y| image::Rgb([(x * 4) as u8, (y * 8) as u8, 128])).save(directory.join("photos/cat.png")).unwrap();
+        let config = crate::config::Config {
+            source : directory.clone(),
+            images : crate::config::ImageSettings {
+                widths : vec![16, 128],
+                format : "webp".to_string(),
+                cache : directory.join("cache")
+            },
+            ..crate::config::Config::default()
+        };
+        let mut images = Images::new(&config);
+        let attributes = images.attributes("/photos/cat.png").unwrap();
+        assert!(attributes.contains("width=\"64\" height=\"32\""), "{}", attributes);
+        let cached : Vec<PathBuf> = images.generated().map(|(_, cached)| cached.clone()).collect();
+        assert_eq!(cached.len(), 2);
+        for file in &cached { // a miss writes the cache, and nothing half-written is left behind
+            assert!(file.exists());
+        }
+        assert_eq!(std::fs::read_dir(directory.join("cache")).unwrap().count(), 2);
+        std::fs::write(&cached[0], "cached").unwrap(); // a hit uses the cache as it is, without resizing again
+        let mut again = Images::new(&config);
+        assert_eq!(again.attributes("/photos/cat.png").unwrap(), attributes);
+        assert_eq!(std::fs::read_to_string(&cached[0]).unwrap(), "cached");
+        image::RgbImage::new(64, 32).save(directory.join("photos/cat.png")).unwrap(); // a different image is a miss
+        let mut changed = Images::new(&config);
+        changed.attributes("/photos/cat.png").unwrap();
+        assert_eq!(std::fs::read_dir(directory.join("cache")).unwrap().count(), 4);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
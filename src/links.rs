@@ -0,0 +1,271 @@
+/*  Internal link checking, run on the finished output right before it's written. Every href, src, srcset and poster in every rendered
+    HTML page is resolved against the output tree, the way a static file server would: root-relative links (/about/) from the output
+    directory, relative ones (../about.html) from the page's own directory, and absolute links to base_url as if they were root-relative.
+    A link to a directory, or to a path with no file, is fine if there's an index.html there. Fragments (#section) have to match an id
+    (or an <a name>) in the page they point at. External links, mailto: and the like aren't checked.
+    Problems are warnings; with --strict they fail the build.
+*/
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+
+pub const LINK_ATTRIBUTES : &[&str] = &["href", "src", "srcset", "poster"];
+pub const RAW_ELEMENTS : &[&str] = &["script", "style"];
+
+
+#[derive(Default)]
+struct Document {
+    links : Vec<String>,
+    ids : HashSet<String>
+}
+
+
+fn scan(html : &str) -> Document { // pull the links and ids out of a page
+    let mut ret = Document::default();
+    let lower = html.to_ascii_lowercase(); // same byte offsets as html, for finding closing tags
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        if rest.starts_with("<!--") {
+            rest = rest.find("-->").map(|end| &rest[end + 3..]).unwrap_or("");
+            continue;
+        }
+        let name : String = rest[1..].chars().take_while(|c| c.is_ascii_alphanumeric()).collect::<String>().to_ascii_lowercase();
+        if name.is_empty() {
+            rest = &rest[1..];
+            continue;
+        }
+        let (attributes, end) = read_attributes(&rest[1 + name.len()..]);
+        rest = &rest[1 + name.len() + end..];
+        for (key, value) in attributes {
+            match key.as_str() {
+                "srcset" => ret.links.extend(value.split(',').filter_map(|candidate| candidate.split_whitespace().next()).map(|url| url.to_string())),
+                "id" => { ret.ids.insert(value); },
+                "name" if name == "a" => { ret.ids.insert(value); },
+                key if LINK_ATTRIBUTES.contains(&key) => ret.links.push(value),
+                _ => {}
+            }
+        }
+        if RAW_ELEMENTS.contains(&name.as_str()) {
+            let close = format!("</{}", name);
+            let at = html.len() - rest.len();
+            rest = lower[at..].find(&close).map(|end| &html[at + end..]).unwrap_or("");
+        }
+    }
+    ret
+}
+
+
+fn read_attributes(tag : &str) -> (Vec<(String, String)>, usize) { // the inside of a tag, after its name -> (attributes, bytes up to and including the >)
+    let (spans, end) = attribute_spans(tag);
+    (spans.into_iter().map(|(key, span)| (key, decode_entities(&tag[span]))).collect(), end)
+}
+
+
+pub fn attribute_spans(tag : &str) -> (Vec<(String, std::ops::Range<usize>)>, usize) { // same, but with where each value is, as written
+    let mut ret = vec![];
+    let bytes = tag.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c == b'>' {
+            return (ret, i + 1);
+        }
+        if c.is_ascii_whitespace() || c == b'/' {
+            i += 1;
+            continue;
+        }
+        let key_start = i;
+        while i < bytes.len() && !bytes[i].is_ascii_whitespace() && !b"=>/".contains(&bytes[i]) {
+            i += 1;
+        }
+        let key = tag[key_start..i].to_ascii_lowercase();
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i >= bytes.len() || bytes[i] != b'=' {
+            ret.push((key, i..i));
+            continue;
+        }
+        i += 1;
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        let value = if i < bytes.len() && (bytes[i] == b'"' || bytes[i] == b'\'') {
+            let quote = bytes[i];
+            let end = tag[i + 1..].bytes().position(|b| b == quote).map(|e| i + 1 + e).unwrap_or(bytes.len());
+            let value = i + 1..end;
+            i = (end + 1).min(bytes.len());
+            value
+        }
+        else {
+            let start = i;
+            while i < bytes.len() && !bytes[i].is_ascii_whitespace() && bytes[i] != b'>' {
+                i += 1;
+            }
+            start..i
+        };
+        ret.push((key, value));
+    }
+    (ret, bytes.len())
+}
+
+
+fn decode_entities(text : &str) -> String { // just the ones that turn up in URLs
+    text.replace("&quot;", "\"").replace("&#39;", "'").replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&")
+}
+
+
+pub fn percent_decode(text : &str) -> String {
+    let bytes = text.as_bytes();
+    let mut ret = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Some(byte) = std::str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                ret.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        ret.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&ret).to_string()
+}
+
+
+pub fn is_external(url : &str) -> bool { // //cdn.example.com/x.js, https://..., mailto:..., data:...
+    if url.starts_with("//") {
+        return true;
+    }
+    match url.find(':') {
+        Some(colon) => {
+            let scheme = &url[..colon];
+            scheme.starts_with(|c : char| c.is_ascii_alphabetic()) && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        },
+        None => false
+    }
+}
+
+
+fn resolve(page : &Path, url : &str, base_url : Option<&str>) -> Option<Result<(PathBuf, bool, String), String>> {
+    // a link on a page -> Some(Ok((path in the output directory, whether it was written as a directory, fragment))), Some(Err) if it leaves
+    // the output directory, or None if it isn't ours to check.
+    let mut url = url.trim();
+    if let Some(base_url) = base_url.map(|b| b.trim_end_matches('/')).filter(|b| !b.is_empty()) {
+        if let Some(rest) = url.strip_prefix(base_url) {
+            if rest.is_empty() || rest.starts_with(['/', '?', '#']) {
+                url = if rest.is_empty() { "/" } else { rest };
+            }
+        }
+    }
+    if url.is_empty() || is_external(url) {
+        return None;
+    }
+    let (url, fragment) = url.split_once('#').unwrap_or((url, ""));
+    let url = url.split('?').next().unwrap();
+    if url.is_empty() {
+        return Some(Ok((page.to_path_buf(), false, percent_decode(fragment))));
+    }
+    let mut ret = if url.starts_with('/') { PathBuf::new() } else { page.parent().map(|p| p.to_path_buf()).unwrap_or_default() };
+    for segment in url.split('/') {
+        match segment {
+            "" | "." => {},
+            ".." => {
+                if !ret.pop() {
+                    return Some(Err(format!("{} points outside the site", url)));
+                }
+            },
+            segment => ret.push(percent_decode(segment))
+        }
+    }
+    let directory = url.ends_with('/') || url.ends_with("/.") || url.ends_with("/..") || url == "." || url == "..";
+    Some(Ok((ret, directory, percent_decode(fragment))))
+}
+
+
+pub fn check(base_url : Option<&str>, pages : &[(PathBuf, PathBuf, &str)], exists : &dyn Fn(&Path) -> bool) -> Vec<String> {
+    // pages is (output path, source path, rendered HTML) for every page. exists says whether a file will be in the output directory.
+    let documents : BTreeMap<&Path, Document> = pages.iter().map(|(path, _, html)| (path.as_path(), scan(html))).collect();
+    let mut ret = vec![];
+    for (path, source, _) in pages {
+        for url in &documents[path.as_path()].links {
+            let (target, directory, fragment) = match resolve(path, url, base_url) {
+                Some(Ok(target)) => target,
+                Some(Err(e)) => {
+                    ret.push(format!("{}: {}", source.display(), e));
+                    continue;
+                },
+                None => continue
+            };
+            let index = target.join("index.html");
+            let found = if directory || target.as_os_str().is_empty() {
+                Some(index).filter(|index| exists(index))
+            }
+            else if exists(&target) {
+                Some(target)
+            }
+            else {
+                Some(index).filter(|index| exists(index))
+            };
+            let found = match found {
+                Some(found) => found,
+                None => {
+                    ret.push(format!("{}: broken link to {}", source.display(), url));
+                    continue;
+                }
+            };
+            if fragment.is_empty() || fragment == "top" { // browsers handle #top themselves
+                continue;
+            }
+            if let Some(document) = documents.get(found.as_path()) {
+                if !document.ids.contains(&fragment) {
+                    ret.push(format!("{}: {} links to a missing anchor in {}", source.display(), url, found.display()));
+                }
+            }
+        }
+    }
+    ret
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_finds_links_and_ids() {
+        let document = scan("<A HREF=\"/about/\">x</A><img src='cat.jpg' srcset=\"cat-480.jpg 480w, cat-960.jpg 960w\"><h2 id=\"top-story\"></h2><a name=old></a>\
+            <!-- <a href=\"/commented/\"> --><SCRIPT>if (a<b) { s = '<a href=\"/in-script/\">'; }</script><p id=after></p>");
+        assert_eq!(document.links, vec!["/about/", "cat.jpg", "cat-480.jpg", "cat-960.jpg"]);
+        let mut ids : Vec<&str> = document.ids.iter().map(|id| id.as_str()).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["after", "old", "top-story"]);
+    }
+
+    #[test]
+    fn links_resolve_like_a_file_server() {
+        let page = Path::new("blog/post/index.html");
+        let resolved = |url : &str| resolve(page, url, Some("https://example.com/"));
+        assert_eq!(resolved("/about/"), Some(Ok((PathBuf::from("about"), true, String::new()))));
+        assert_eq!(resolved("../other.html#part"), Some(Ok((PathBuf::from("blog/other.html"), false, "part".to_string()))));
+        assert_eq!(resolved("https://example.com/a%20b.html?x=1"), Some(Ok((PathBuf::from("a b.html"), false, String::new()))));
+        assert_eq!(resolved("#here"), Some(Ok((page.to_path_buf(), false, "here".to_string()))));
+        assert_eq!(resolved("https://elsewhere.com/"), None);
+        assert_eq!(resolved("mailto:me@example.com"), None);
+        assert!(matches!(resolved("../../../up.html"), Some(Err(_))));
+    }
+
+    #[test]
+    fn check_reports_broken_links_and_anchors() {
+        let pages = [(PathBuf::from("index.html"), PathBuf::from("src/index.html"), "<a href=\"/about/#team\"></a><a href=\"/gone/\"></a>"),
+            (PathBuf::from("about/index.html"), PathBuf::from("src/about.html"), "<h2 id=\"people\"></h2>")];
+        let exists = |path : &Path| pages.iter().any(|(page, _, _)| page == path);
+        assert_eq!(check(None, &pages, &exists), vec![
+            "src/index.html: /about/#team links to a missing anchor in about/index.html".to_string(),
+            "src/index.html: broken link to /gone/".to_string()
+        ]);
+    }
+}
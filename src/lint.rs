@@ -0,0 +1,58 @@
+/*  Lints: things in pages and templates that are probably mistakes. They're only warnings, so the site still builds, unless sitix is run
+    with --deny-warnings (for CI), which makes any of them (and any broken link) fail the build. There are three kinds:
+
+    undefined-variable   [^x] with no x and no default, which is usually a typo. [^x-][/] says that nothing is a fine default.
+    unused-assignment    [=x ...] when nothing in the page or its template reads x. Names starting with _ are never reported.
+    reserved-name        a variable called page, site or parent, which can't be read (see rasta.rs).
+    A [=template x] with no x in the templates directory isn't a lint: the page can't be rendered, so it's an error.
+
+    Turn kinds off for a single page with [=allow undefined-variable, unused-assignment] in its content. In a template, that does the
+    same for the template and every page that uses it. For the whole site, use sitix.toml:
+
+[lint]
+allow = ["unused-assignment"]
+*/
+
+use crate::rasta::{TreeNode, Warning};
+use std::path::Path;
+
+
+pub const METADATA : &[&str] = &["template", "permalink", "sitemap", "title", "date", "summary", "allow"]; // read by sitix itself, not by Rasta
+
+
+#[derive(Default)]
+pub struct Linter {
+    pub allow : Vec<String>
+}
+
+
+impl Linter {
+    pub fn filter(&self, source : &Path, allowed : &[String], warnings : Vec<Warning>) -> Vec<String> { // the warnings that aren't turned off, ready to print
+        warnings.into_iter().filter(|warning| !self.allow.iter().chain(allowed).any(|kind| kind == warning.kind))
+            .map(|warning| format!("{}: {} [{}]", source.display(), warning.message, warning.kind)).collect()
+    }
+}
+
+
+pub fn allowed(tree : &TreeNode) -> Vec<String> { // the kinds a [=allow ...] turns off
+    tree.assignment("allow").map(|kinds| kinds.split([',', ' ']).filter(|kind| !kind.is_empty()).map(|kind| kind.to_string()).collect()).unwrap_or_default()
+}
+
+
+pub fn unused(tree : &TreeNode, readers : &[&TreeNode], ignore : &[String]) -> Vec<Warning> { // assignments in tree that nothing in tree or readers reads
+    let mut references = tree.references();
+    for reader in readers {
+        references.extend(reader.references());
+    }
+    let mut ret = vec![];
+    for name in tree.assignments() {
+        if name.starts_with('_') || references.contains(&name) || METADATA.contains(&name.as_str()) || crate::rasta::RESERVED.contains(&name.as_str()) || ignore.contains(&name) {
+            continue;
+        }
+        let warning = Warning::new("unused-assignment", format!("{} is set but never read", name));
+        if !ret.contains(&warning) {
+            ret.push(warning);
+        }
+    }
+    ret
+}
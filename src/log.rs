@@ -0,0 +1,165 @@
+/*  Logging. Everything sitix has to say goes through here, so it can be turned up, turned down, or turned into JSON.
+    There are five levels; sitix shows up to info unless told otherwise:
+    error   the build failed, and why
+    warn    lints, broken links, and anything else that's probably a mistake
+    info    what the build is up to, one line per page, and the summary at the end
+    debug   one line per asset, and other details (-v)
+    trace   every file written, and everything else (-vv)
+    -q shows only warnings and errors.
+
+    Human output goes to stdout, apart from warnings and errors, which go to stderr (in color, if it's a terminal and NO_COLOR isn't set).
+    With --log-format json, everything is one JSON object per line on stdout, like
+{"time":"2024-03-01T12:30:00Z","level":"info","event":"page","message":"Rendered about.html","source":"about.html",...}
+    Most events are just "message"; the ones worth graphing ("page", "summary") have their numbers as fields of their own.
+    Results, like the output of --diff, aren't news about the build, so they're printed whatever the level (with level "result" in JSON).
+*/
+
+use std::io::{IsTerminal, Write};
+
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace
+}
+
+
+impl Level {
+    fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace"
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum Format {
+    Human,
+    Json
+}
+
+
+struct Settings {
+    level : Level,
+    format : Format,
+    color : bool
+}
+
+
+static SETTINGS : std::sync::OnceLock<Settings> = std::sync::OnceLock::new();
+
+
+pub fn init(level : Level, format : Format) { // call once, before logging anything. Until then it's info, for humans, without color.
+    let color = format == Format::Human && std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    let _ = SETTINGS.set(Settings {
+        level,
+        format,
+        color
+    });
+}
+
+
+fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(|| Settings {
+        level : Level::Info,
+        format : Format::Human,
+        color : false
+    })
+}
+
+
+pub fn enabled(level : Level) -> bool {
+    level <= settings().level
+}
+
+
+pub fn event(level : Level, name : &str, message : &str, fields : serde_json::Value) { // fields should be a JSON object; humans only see the message
+    if !enabled(level) {
+        return;
+    }
+    let settings = settings();
+    match settings.format {
+        Format::Human => {
+            if level <= Level::Warn {
+                let (color, label) = if level == Level::Error { ("31", "error") } else { ("33", "warning") };
+                let message = message.trim_start();
+                let mut stderr = std::io::stderr().lock();
+                let _ = if settings.color {
+                    writeln!(stderr, "\x1b[1;{}m{}:\x1b[0m {}", color, label, message)
+                }
+                else {
+                    writeln!(stderr, "{}: {}", label, message)
+                };
+            }
+            else {
+                let _ = writeln!(std::io::stdout().lock(), "{}", message); // not println!, which panics if stdout is a closed pipe (sitix | head)
+            }
+        },
+        Format::Json => {
+            let mut line = serde_json::json!({
+                "time" : crate::dates::rfc3339(crate::dates::unix_seconds(std::time::SystemTime::now())),
+                "level" : level.name(),
+                "event" : name,
+                "message" : message.trim()
+            });
+            if let (Some(line), serde_json::Value::Object (fields)) = (line.as_object_mut(), fields) {
+                line.extend(fields);
+            }
+            let _ = writeln!(std::io::stdout().lock(), "{}", line);
+        }
+    }
+}
+
+
+pub fn result(name : &str, message : &str, fields : serde_json::Value) {
+    // something the user asked for, like --diff, rather than news about the build: always printed, to stdout, whatever the level
+    match settings().format {
+        Format::Human => {
+            let _ = write!(std::io::stdout().lock(), "{}", message);
+        },
+        Format::Json => {
+            let mut line = serde_json::json!({
+                "time" : crate::dates::rfc3339(crate::dates::unix_seconds(std::time::SystemTime::now())),
+                "level" : "result",
+                "event" : name
+            });
+            if let (Some(line), serde_json::Value::Object (fields)) = (line.as_object_mut(), fields) {
+                line.extend(fields);
+            }
+            let _ = writeln!(std::io::stdout().lock(), "{}", line);
+        }
+    }
+}
+
+
+pub fn error(message : &str) {
+    event(Level::Error, "message", message, serde_json::Value::Null);
+}
+
+
+pub fn warn(message : &str) {
+    event(Level::Warn, "message", message, serde_json::Value::Null);
+}
+
+
+pub fn info(message : &str) {
+    event(Level::Info, "message", message, serde_json::Value::Null);
+}
+
+
+pub fn debug(message : &str) {
+    event(Level::Debug, "message", message, serde_json::Value::Null);
+}
+
+
+pub fn trace(message : &str) {
+    event(Level::Trace, "message", message, serde_json::Value::Null);
+}
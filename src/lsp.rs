@@ -0,0 +1,550 @@
+/*  sitix lsp: a language server for Rasta, for any editor that speaks LSP. The editor starts it (sitix lsp) and talks JSON-RPC to it over
+    stdin and stdout; it reads sitix.toml from the workspace root for the templates directory and the site variables. It offers
+    diagnostics   syntax errors as you type, and [=template x] when there's no x in the templates directory
+    definition    from [^x] (or the list in [*item x-]) to the [=x] it reads, in the same file or the page's template, and from
+                  [=template x] to x
+    completion    variable names after [^ and [*, template names after [=template, and function names after [@
+    hover         what a variable is set to, and where
+    Variables are found the way Rasta finds them (see rasta.rs), as far as that can be done without rendering: the nearest assignment
+    in a block around the cursor wins. Only whole-document sync is supported, which every editor can do.
+*/
+
+use crate::rasta::{self, Scope, ScopeKind, SourceToken};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+
+const PARSE_ERROR : i64 = -32700;
+const METHOD_NOT_FOUND : i64 = -32601;
+
+
+struct Node { // a tag, and where it is
+    control : char,
+    name : String,
+    rest : String, // everything after the name: an assignment's value, a default, a loop's list
+    start : usize, // in characters, like SourceTag
+    end : usize,
+    body : Option<(usize, usize)>, // for extended tags, what's between the tag and its [/]
+    parent : Option<usize> // the extended tag it's inside
+}
+
+
+fn outline(text : &str) -> Vec<Node> { // every tag that means something, as far as they go even if the file doesn't parse
+    let mut ret : Vec<Node> = vec![];
+    let mut open : Vec<usize> = vec![];
+    for token in rasta::source_tokens(text) {
+        let tag = match token {
+            SourceToken::Tag (tag) => tag,
+            SourceToken::Text (_) => continue
+        };
+        if tag.control == '/' {
+            if let Some(index) = open.pop() {
+                ret[index].body = ret[index].body.map(|(start, _)| (start, tag.start));
+            }
+            continue;
+        }
+        if matches!(tag.control, '#' | '%') {
+            continue;
+        }
+        let (content, _) = tag.inside();
+        let content = content.strip_suffix('-').map(|stripped| stripped.trim().to_string()).unwrap_or(content);
+        let (name, rest) = content.split_once(' ').map(|(name, rest)| (name.to_string(), rest.trim().to_string())).unwrap_or((content, String::new()));
+        ret.push(Node {
+            control : tag.control,
+            name,
+            rest,
+            start : tag.start,
+            end : tag.end,
+            body : tag.opens_block().then_some((tag.end, text.chars().count())),
+            parent : open.last().copied()
+        });
+        if tag.opens_block() {
+            open.push(ret.len() - 1);
+        }
+    }
+    ret
+}
+
+
+fn locate_error(text : &str) -> Option<(usize, usize)> { // where the parser's complaint probably is: the first unknown tag, a [/] with nothing to close, or the innermost tag never closed
+    let mut open = vec![];
+    let tokens = rasta::source_tokens(text);
+    for token in &tokens { // the lexer complains about these before the parser sees anything
+        if let SourceToken::Tag (tag) = token {
+            if tag.unterminated() {
+                return Some((tag.start, tag.end - tag.body.as_ref().map(|body| body.chars().count()).unwrap_or(0)));
+            }
+        }
+    }
+    for token in tokens {
+        if let SourceToken::Tag (tag) = token {
+            match tag.control {
+                '/' => match open.pop() {
+                    None => return Some((tag.start, tag.end)),
+                    Some(_) if open.is_empty() => return None, // the parser stops at the end of the header
+                    Some(_) => {}
+                },
+                '#' | '%' => {},
+                '=' | '^' | '@' | '*' => {
+                    if tag.opens_block() {
+                        open.push((tag.start, tag.end));
+                    }
+                },
+                _ => return Some((tag.start, tag.end))
+            }
+        }
+    }
+    open.pop()
+}
+
+
+fn position(text : &str, offset : usize) -> Value { // a character offset -> an LSP position, which counts columns in UTF-16
+    let (mut line, mut character) = (0, 0);
+    for c in text.chars().take(offset) {
+        if c == '\n' {
+            line += 1;
+            character = 0;
+        }
+        else {
+            character += c.len_utf16();
+        }
+    }
+    json!({ "line" : line, "character" : character })
+}
+
+
+fn offset(text : &str, position : &Value) -> usize {
+    let line = position["line"].as_u64().unwrap_or(0) as usize;
+    let character = position["character"].as_u64().unwrap_or(0) as usize;
+    let (mut ret, mut current, mut column) = (0, 0, 0);
+    for c in text.chars() {
+        if current == line && (column >= character || c == '\n') {
+            break;
+        }
+        if c == '\n' {
+            current += 1;
+        }
+        else if current == line {
+            column += c.len_utf16();
+        }
+        ret += 1;
+    }
+    ret
+}
+
+
+fn range(text : &str, start : usize, end : usize) -> Value {
+    json!({ "start" : position(text, start), "end" : position(text, end) })
+}
+
+
+fn uri_to_path(uri : &str) -> Option<PathBuf> {
+    uri.strip_prefix("file://").map(|path| PathBuf::from(crate::links::percent_decode(path)))
+}
+
+
+fn path_to_uri(path : &Path) -> String {
+    let mut ret = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            ret.push(byte as char);
+        }
+        else {
+            ret += &format!("%{:02X}", byte);
+        }
+    }
+    ret
+}
+
+
+struct Found { // where a variable is set
+    uri : String,
+    text : String,
+    nodes : Vec<Node>,
+    index : usize
+}
+
+
+struct Server {
+    config : crate::config::Config,
+    site : Rc<RefCell<Scope>>,
+    documents : HashMap<String, String> // uri -> text, for everything the editor has open
+}
+
+
+impl Server {
+    fn new(root : &Path) -> Self {
+        let config = crate::config::Config::load(root).unwrap_or_else(|e| {
+            crate::log::warn(&format!("Invalid configuration: {}", e));
+            crate::config::Config::default()
+        });
+        let site = Scope::chitlin_kind_w(Scope::top().wrap(), "site".to_string(), ScopeKind::Site);
+        config.fill_scope(site.clone());
+        Self {
+            config,
+            site,
+            documents : HashMap::new()
+        }
+    }
+
+    fn text(&self, path : &Path) -> Option<String> { // what's in a file, as the editor has it if it's open
+        self.documents.get(&path_to_uri(path)).cloned().or_else(|| std::fs::read_to_string(path).ok())
+    }
+
+    fn templates(&self) -> Vec<PathBuf> {
+        crate::template_paths(&self.config).unwrap_or_default() // the same ones a build would load
+    }
+
+    fn template(&self, name : &str) -> Option<PathBuf> {
+        self.templates().into_iter().find(|path| path.file_stem().is_some_and(|stem| stem == name))
+    }
+
+    fn is_template(&self, uri : &str) -> bool {
+        let templates = self.config.templates.canonicalize().unwrap_or(self.config.templates.clone());
+        uri_to_path(uri).and_then(|path| path.canonicalize().ok()).is_some_and(|path| path.starts_with(templates))
+    }
+
+    fn page_template(&self, uri : &str, nodes : &[Node]) -> Option<PathBuf> { // the template a page renders into
+        if self.is_template(uri) {
+            return None;
+        }
+        let name = nodes.iter().find(|node| node.control == '=' && node.name == "template" && node.parent == Some(0) && !node.rest.is_empty())
+            .map(|node| node.rest.clone()).unwrap_or(self.config.default_template.clone());
+        self.template(&name)
+    }
+
+    fn diagnostics(&self, text : &str) -> Vec<Value> {
+        let mut ret = vec![];
+        if let Err(e) = rasta::TreeNode::parse_str(text) {
+            let (start, end) = locate_error(text).unwrap_or((0, 0));
+            ret.push(json!({ "range" : range(text, start, end), "severity" : 1, "source" : "sitix", "message" : e.0 }));
+        }
+        for node in outline(text) {
+            if node.control == '=' && node.name == "template" && !node.rest.is_empty() && self.template(&node.rest).is_none() {
+                ret.push(json!({
+                    "range" : range(text, node.start, node.end),
+                    "severity" : 1, // an error, like it is in a build
+                    "source" : "sitix",
+                    "code" : "unknown-template",
+                    "message" : format!("there's no {} template in {}", node.rest, self.config.templates.display())
+                }));
+            }
+        }
+        ret
+    }
+
+    fn find(nodes : &[Node], parts : &[&str], from : Option<usize>) -> Option<usize> { // the assignment (or loop) parts reads, seen from the tag at from
+        let ancestors : Vec<usize> = std::iter::successors(from.and_then(|from| nodes[from].parent), |&index| nodes[index].parent).collect();
+        let depth = |index : usize| std::iter::successors(nodes[index].parent, |&parent| nodes[parent].parent).count();
+        let candidates = (0..nodes.len()).filter(|&index| matches!(nodes[index].control, '=' | '*') && nodes[index].name == parts[0] && Some(index) != from);
+        let visible = candidates.filter(|&index| from.is_none() || nodes[index].parent.is_none_or(|parent| ancestors.contains(&parent)));
+        let mut found = visible.max_by_key(|&index| (depth(index), from.is_none_or(|from| nodes[index].start < nodes[from].start), nodes[index].start))?;
+        for part in &parts[1..] {
+            match (0..nodes.len()).find(|&index| nodes[index].parent == Some(found) && nodes[index].control == '=' && nodes[index].name == *part) {
+                Some(index) => found = index,
+                None => break
+            }
+        }
+        Some(found)
+    }
+
+    fn resolve(&self, uri : &str, text : &str, at : usize) -> Option<(String, Option<Found>)> {
+        // the variable under the cursor, and where it's set (if that's in a page or template, rather than sitix.toml)
+        let nodes = outline(text);
+        let index = (0..nodes.len()).rev().find(|&index| nodes[index].start <= at && at < nodes[index].end)?;
+        let name = match nodes[index].control {
+            '^' => nodes[index].name.clone(),
+            '*' => nodes[index].rest.clone(),
+            _ => return None
+        };
+        let mut parts : Vec<&str> = name.split('.').collect();
+        match parts[0] {
+            "site" => return Some((name, None)),
+            "page" | "parent" => {
+                parts.remove(0);
+            },
+            _ => {}
+        }
+        if parts.is_empty() {
+            return Some((name, None));
+        }
+        if let Some(found) = Server::find(&nodes, &parts, Some(index)) {
+            return Some((name, Some(Found { uri : uri.to_string(), text : text.to_string(), nodes, index : found })));
+        }
+        let template = self.page_template(uri, &nodes)?;
+        let template_text = self.text(&template)?;
+        let template_nodes = outline(&template_text);
+        let found = Server::find(&template_nodes, &parts, None);
+        Some((name, found.map(|index| Found { uri : path_to_uri(&template), text : template_text, nodes : template_nodes, index })))
+    }
+
+    fn definition(&self, uri : &str, text : &str, at : usize) -> Value {
+        let nodes = outline(text);
+        if let Some(node) = nodes.iter().rev().find(|node| node.start <= at && at < node.end) {
+            if node.control == '=' && node.name == "template" {
+                return match self.template(&node.rest) {
+                    Some(path) => json!({ "uri" : path_to_uri(&path), "range" : range("", 0, 0) }),
+                    None => Value::Null
+                };
+            }
+        }
+        match self.resolve(uri, text, at) {
+            Some((_, Some(found))) => {
+                let node = &found.nodes[found.index];
+                json!({ "uri" : found.uri, "range" : range(&found.text, node.start, node.end) })
+            },
+            _ => Value::Null
+        }
+    }
+
+    fn hover(&self, uri : &str, text : &str, at : usize) -> Value {
+        let (name, found) = match self.resolve(uri, text, at) {
+            Some(resolved) => resolved,
+            None => return Value::Null
+        };
+        let (value, place) = match found {
+            Some(found) => {
+                let node = &found.nodes[found.index];
+                let value = if node.control == '*' {
+                    format!("each variable in {}", node.rest)
+                }
+                else if !node.rest.is_empty() || node.body.is_none() {
+                    node.rest.clone()
+                }
+                else {
+                    let (start, end) = node.body.unwrap();
+                    found.text.chars().skip(start).take(end - start).collect::<String>().trim().to_string()
+                };
+                let place = if found.uri == uri { String::new() } else { format!(" (set in {})", uri_to_path(&found.uri).unwrap_or_default().display()) };
+                (value, place)
+            },
+            None => {
+                match self.site.borrow().get(name.strip_prefix("site.").unwrap_or(&name).to_string()) {
+                    Some(value) => (value, " (from sitix.toml)".to_string()),
+                    None => return Value::Null
+                }
+            }
+        };
+        let value = if value.chars().count() > 500 { format!("{}...", value.chars().take(500).collect::<String>()) } else { value };
+        json!({ "contents" : { "kind" : "markdown", "value" : format!("**{}**{}\n```html\n{}\n```", name, place, value) } })
+    }
+
+    fn completion(&self, uri : &str, text : &str, at : usize) -> Value {
+        let before : String = text.chars().take(at).collect();
+        let tag = match before.rfind('[') {
+            Some(open) if !before[open..].contains([']', '\n']) => before[open + 1..].trim_start_matches('~').trim_start().to_string(),
+            _ => return json!([])
+        };
+        let mut items : Vec<(String, u8)> = vec![]; // (label, LSP completion kind)
+        if let Some(typed) = tag.strip_prefix('=').and_then(|tag| tag.strip_prefix("template ")) {
+            items.extend(self.templates().iter().filter_map(|path| path.file_stem()).map(|stem| (stem.to_string_lossy().to_string(), 17)).filter(|(name, _)| name.starts_with(typed.trim())));
+        }
+        else if tag.starts_with('@') {
+            items.extend(crate::FUNCTIONS.iter().map(|name| (name.to_string(), 3)));
+        }
+        else if tag.starts_with('^') || tag.starts_with('*') {
+            let nodes = outline(text);
+            let mut names = vec!["page".to_string(), "site".to_string(), "parent".to_string()];
+            if self.is_template(uri) {
+                names.push("content".to_string());
+            }
+            names.extend(Server::variables(&nodes));
+            if let Some(template) = self.page_template(uri, &nodes).and_then(|template| self.text(&template)) {
+                names.extend(Server::variables(&outline(&template)));
+            }
+            names.extend(self.site.borrow().names().into_iter().map(|name| format!("site.{}", name)));
+            items.extend(names.into_iter().map(|name| (name, 6)));
+        }
+        items.sort();
+        items.dedup();
+        Value::Array(items.into_iter().map(|(label, kind)| json!({ "label" : label, "kind" : kind })).collect())
+    }
+
+    fn variables(nodes : &[Node]) -> Vec<String> { // every variable set in a file: [=a-][=b x][/] sets a, and a.b (just b from inside a)
+        let mut ret = vec![];
+        for node in nodes.iter().filter(|node| matches!(node.control, '=' | '*') && !node.name.is_empty()) {
+            ret.push(node.name.clone());
+            let mut name = node.name.clone();
+            let mut parent = node.parent;
+            while let Some(index) = parent.filter(|&index| node.control == '=' && nodes[index].control == '=' && !nodes[index].name.is_empty()) {
+                name = format!("{}.{}", nodes[index].name, name);
+                ret.push(name.clone());
+                parent = nodes[index].parent;
+            }
+        }
+        ret
+    }
+}
+
+
+fn read_message(input : &mut impl BufRead) -> Result<Option<Result<Value, String>>, std::io::Error> {
+    // one JSON-RPC message (or why it isn't one, so the editor can be told and the server can carry on), or None once the editor hangs up
+    let mut length = Err("a message with no Content-Length".to_string());
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().map_err(|e| format!("bad Content-Length {:?}: {}", value.trim(), e));
+            }
+        }
+    }
+    let length = match length {
+        Ok(length) => length,
+        Err(e) => return Ok(Some(Err(e)))
+    };
+    let mut body = vec![0; length];
+    match input.read_exact(&mut body) {
+        Ok(()) => Ok(Some(serde_json::from_slice(&body).map_err(|e| e.to_string()))),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e)
+    }
+}
+
+
+fn send(output : &mut impl Write, message : Value) -> Result<(), std::io::Error> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+
+pub fn run() -> Result<(), Box<dyn std::error::Error + 'static>> {
+    let mut input = std::io::stdin().lock();
+    let mut output = std::io::stdout().lock();
+    let mut server = Server::new(&std::env::current_dir()?);
+    while let Some(message) = read_message(&mut input)? {
+        let message = match message {
+            Ok(message) => message,
+            Err(e) => {
+                send(&mut output, json!({ "jsonrpc" : "2.0", "id" : null, "error" : { "code" : PARSE_ERROR, "message" : e } }))?;
+                continue;
+            }
+        };
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let id = message.get("id").cloned();
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+        let result = match method {
+            "initialize" => {
+                if let Some(root) = params["rootUri"].as_str().and_then(uri_to_path).or_else(|| params["rootPath"].as_str().map(PathBuf::from)) {
+                    server = Server::new(&root);
+                }
+                json!({
+                    "capabilities" : {
+                        "textDocumentSync" : 1,
+                        "definitionProvider" : true,
+                        "hoverProvider" : true,
+                        "completionProvider" : { "triggerCharacters" : ["^", "*", "@", " ", "."] }
+                    },
+                    "serverInfo" : { "name" : "sitix", "version" : env!("CARGO_PKG_VERSION") }
+                })
+            },
+            "shutdown" => Value::Null,
+            "exit" => return Ok(()),
+            "textDocument/didOpen" | "textDocument/didChange" | "textDocument/didClose" => {
+                let text = match method {
+                    "textDocument/didOpen" => params["textDocument"]["text"].as_str().map(|text| text.to_string()),
+                    "textDocument/didChange" => params["contentChanges"].as_array().and_then(|changes| changes.last()).and_then(|change| change["text"].as_str()).map(|text| text.to_string()),
+                    _ => None
+                };
+                let diagnostics = match text {
+                    Some(text) => {
+                        let diagnostics = server.diagnostics(&text);
+                        server.documents.insert(uri.clone(), text);
+                        diagnostics
+                    },
+                    None => {
+                        server.documents.remove(&uri);
+                        vec![]
+                    }
+                };
+                send(&mut output, json!({ "jsonrpc" : "2.0", "method" : "textDocument/publishDiagnostics", "params" : { "uri" : uri, "diagnostics" : diagnostics } }))?;
+                continue;
+            },
+            "textDocument/definition" | "textDocument/hover" | "textDocument/completion" => {
+                let text = server.documents.get(&uri).cloned().unwrap_or_default();
+                let at = offset(&text, &params["position"]);
+                match method {
+                    "textDocument/definition" => server.definition(&uri, &text, at),
+                    "textDocument/hover" => server.hover(&uri, &text, at),
+                    _ => server.completion(&uri, &text, at)
+                }
+            },
+            _ => {
+                if let Some(id) = id {
+                    send(&mut output, json!({ "jsonrpc" : "2.0", "id" : id, "error" : { "code" : METHOD_NOT_FOUND, "message" : format!("sitix doesn't do {}", method) } }))?;
+                }
+                continue;
+            }
+        };
+        if let Some(id) = id {
+            send(&mut output, json!({ "jsonrpc" : "2.0", "id" : id, "result" : result }))?;
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_count_utf16() {
+        let text = "[=-]\nh\u{e9}\u{1f600}[^x]\n[/]";
+        assert_eq!(position(text, 8), json!({ "line" : 1, "character" : 4 })); // the emoji is two UTF-16 units
+        assert_eq!(offset(text, &json!({ "line" : 1, "character" : 4 })), 8);
+        assert_eq!(offset(text, &json!({ "line" : 1, "character" : 99 })), 12); // past the end of a line is its end
+        assert_eq!(offset(text, &json!({ "line" : 2, "character" : 1 })), 14);
+    }
+
+    #[test]
+    fn find_picks_the_nearest_visible_assignment() {
+        let text = "[=-][=x outer][=a-][=x inner][^x][/][^x][=b-][=y 1][/][^b.y][/]";
+        let nodes = outline(text);
+        let at = |tag : &str, nth : usize| (0..nodes.len()).filter(|&index| nodes[index].control == tag.chars().next().unwrap() && nodes[index].name == tag[1..]).nth(nth).unwrap();
+        let found = |tag : &str, nth : usize, parts : &[&str]| Server::find(&nodes, parts, Some(at(tag, nth))).map(|index| nodes[index].rest.clone());
+        assert_eq!(found("^x", 0, &["x"]), Some("inner".to_string()));
+        assert_eq!(found("^x", 1, &["x"]), Some("outer".to_string())); // the inner one is out of reach
+        assert_eq!(found("^b.y", 0, &["b", "y"]), Some("1".to_string()));
+        assert_eq!(Server::find(&nodes, &["nothing"], None), None);
+        assert_eq!(found("^b.y", 0, &["y"]), None); // y is only set inside b, so it doesn't resolve on its own
+    }
+
+    #[test]
+    fn unknown_templates_are_errors() {
+        let directory = std::env::temp_dir().join(format!("sitix-lsp-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("_templates")).unwrap();
+        std::fs::write(directory.join("_templates/default.html"), "[^content]").unwrap();
+        let server = Server::new(&directory);
+        let found = server.diagnostics("[=-][=template nope][/]");
+        let known = server.diagnostics("[=-][=template default][/]");
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0]["severity"], 1);
+        assert_eq!(found[0]["code"], "unknown-template");
+        assert!(known.is_empty());
+    }
+
+    #[test]
+    fn messages_are_framed() {
+        let mut input = std::io::Cursor::new(b"Content-Length: 2\r\nContent-Type: x\r\n\r\n{}Content-Length: 5\r\n\r\n{bad}Content-Length: 9\r\n\r\n{}".to_vec());
+        assert_eq!(read_message(&mut input).unwrap(), Some(Ok(json!({}))));
+        assert!(matches!(read_message(&mut input).unwrap(), Some(Err(_)))); // bad JSON is reported, and the next message still reads
+        assert_eq!(read_message(&mut input).unwrap(), None); // cut off
+        let mut input = std::io::Cursor::new(b"X: 1\r\n\r\nContent-Length: 2\r\n\r\n[]".to_vec());
+        assert!(matches!(read_message(&mut input).unwrap(), Some(Err(_))));
+        assert_eq!(read_message(&mut input).unwrap(), Some(Ok(json!([]))));
+    }
+}
@@ -15,127 +15,1123 @@ use clap::Parser;
 use std::io::Write;
 
 pub mod rasta;
+pub mod ignore;
+pub mod config;
+pub mod assets;
+pub mod minify;
+pub mod images;
+pub mod highlight;
+pub mod dates;
+pub mod sitemap;
+pub mod feeds;
+pub mod taxonomy;
+pub mod i18n;
+pub mod links;
+pub mod lint;
+pub mod log;
+pub mod diff;
+pub mod output;
+pub mod fmt;
+pub mod lsp;
+
+
+const EXIT_CODES : &str = "Exit codes: 0 if the site built (warnings or not), 1 if it didn't, 2 for bad arguments, and 3 if it only failed because of --deny-warnings or --strict.";
 
 
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, after_help = EXIT_CODES)]
 struct SitixArgs {
+    #[command(subcommand)]
+    command : Option<Command>,
+    #[command(flatten)]
+    build : BuildArgs // plain `sitix` is `sitix build`
+}
+
+
+#[derive(clap::Subcommand)]
+enum Command {
+    #[command(about = "Build the site (what sitix does with no command)", after_help = EXIT_CODES)]
+    Build (BuildArgs),
+    #[command(about = "Reformat Rasta files: tag spacing and block indentation", after_help = "Exit codes: 0 if everything is formatted (or has been now), 1 if there were files that couldn't be formatted, or with --check, files that need it.")]
+    Fmt (FmtArgs),
+    #[command(about = "Run a language server for Rasta, for editors to start (it talks LSP over stdin and stdout)")]
+    Lsp
+}
+
+
+#[derive(clap::Args)]
+struct LogArgs {
+    #[arg(short, long, help = "Only print warnings and errors")]
+    quiet : bool,
+    #[arg(short, long, action = clap::ArgAction::Count, help = "Print more about what's going on (-v), or everything (-vv)")]
+    verbose : u8,
+    #[arg(long, value_enum, default_value_t = log::Format::Human, help = "Print log messages for people, or as one JSON object per line")]
+    log_format : log::Format
+}
+
+
+impl LogArgs {
+    fn init(&self) {
+        log::init(if self.quiet { log::Level::Warn } else { [log::Level::Info, log::Level::Debug, log::Level::Trace][self.verbose.min(2) as usize] }, self.log_format);
+    }
+}
+
+
+#[derive(clap::Args)]
+struct FmtArgs {
+    #[arg(help = "Files and directories to format; by default, the project's pages and templates")]
+    paths : Vec<std::path::PathBuf>,
+    #[arg(long, help = "Don't change anything, just show what would change, and fail if anything would")]
+    check : bool,
+    #[command(flatten)]
+    log : LogArgs
+}
+
+
+#[derive(clap::Args)]
+struct BuildArgs {
     directory : Option<std::path::PathBuf>,
-    output_directory : Option<std::path::PathBuf>
+    output_directory : Option<std::path::PathBuf>,
+    #[arg(long, help = "Directory containing the pages, overriding sitix.toml")]
+    source : Option<std::path::PathBuf>,
+    #[arg(long, help = "Directory containing the templates, overriding sitix.toml")]
+    templates : Option<std::path::PathBuf>,
+    #[arg(long, help = "Template used by pages that don't pick one, overriding sitix.toml")]
+    default_template : Option<String>,
+    #[arg(long, help = "Absolute URL the site is served from, overriding sitix.toml")]
+    base_url : Option<String>,
+    #[arg(long, help = "Site title, overriding sitix.toml")]
+    title : Option<String>,
+    #[arg(long, help = "Write pages like about.html to about/index.html")]
+    pretty_urls : bool,
+    #[arg(long, help = "Minify rendered HTML pages")]
+    minify : bool,
+    #[arg(long, help = "Fail the build if there are broken internal links")]
+    strict : bool,
+    #[arg(long, help = "Fail the build on any warning: lints and broken links")]
+    deny_warnings : bool,
+    #[command(flatten)]
+    log : LogArgs,
+    #[arg(long, help = "Do everything but write the output (or clean out the old output)")]
+    dry_run : bool,
+    #[arg(long, help = "Show which output files are added, removed or changed, with a unified diff of each text file")]
+    diff : bool
+}
+
+
+enum Output {
+    Rendered (Vec<u8>), // a Rasta page, already rendered
+    Copied // an asset - just copy the source file over
+}
+
+
+struct Page { // a rendered page, for the things that get generated from all of them at the end (like the sitemap)
+    source : std::path::PathBuf, // relative to the source directory
+    path : std::path::PathBuf, // relative to the output directory
+    sitemap : bool,
+    modified : std::time::SystemTime,
+    title : Option<String>,
+    date : Option<i64>,
+    summary : Option<String>,
+    content : String // rendered, but without the template
+}
+
+
+#[derive(Default)]
+struct Build {
+    outputs : std::collections::BTreeMap<std::path::PathBuf, (std::path::PathBuf, Output)>, // output path -> (source path, what to write)
+    pages : Vec<Page>,
+    errors : Vec<String>,
+    denied : Vec<String>, // warnings that --deny-warnings (or --strict) turned into errors
+    warnings : usize,
+    skipped : usize, // sources that didn't end up in the output, like partials and pages with unknown templates
+    deny_warnings : bool,
+    linter : lint::Linter
+}
+
+
+const EXIT_FAILED : u8 = 1; // the build failed, so nothing (or not everything) was written
+const EXIT_DENIED : u8 = 3; // the build worked, but there were warnings and --deny-warnings, or broken links and --strict. (2 is for bad arguments.)
+
+
+pub const FUNCTIONS : [&str; 4] = ["asset_url", "image", "highlight", "t"]; // everything [@...] can call. build() destructures this, so a name can't be listed without being registered
+
+
+const ASSET_EXTENSIONS : &[&str] = &[ // files with these extensions are always copied, without even looking for a Rasta header
+    "png", "jpg", "jpeg", "gif", "webp", "avif", "ico", "bmp", "tif", "tiff", "psd",
+    "woff", "woff2", "ttf", "otf", "eot",
+    "mp3", "mp4", "m4a", "ogg", "oga", "ogv", "wav", "flac", "webm", "mov",
+    "pdf", "zip", "gz", "tar", "7z", "wasm"
+];
+
+
+fn is_asset(config : &config::Config, path : &std::path::Path) -> bool { // true if this file should be copied rather than rendered
+    let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    if ASSET_EXTENSIONS.contains(&extension.as_str()) || config.copy_extensions.iter().any(|e| e.trim_start_matches('.').to_lowercase() == extension) {
+        return true;
+    }
+    !rasta::has_header(path).unwrap_or(false)
+}
+
+
+fn output_path(config : &config::Config, source : &std::path::Path, rendered : bool, permalink : Option<String>) -> Result<std::path::PathBuf, String> {
+    // Figure out where a file goes, relative to the output directory. A permalink wins outright; otherwise the output mirrors the source,
+    // except that with pretty_urls turned on rendered pages like about.html or about.md become about/index.html (but not 404.html, which
+    // servers look for by that name), and pages in a language other than the default go under that language's directory (see i18n.rs).
+    if let Some(permalink) = permalink {
+        let mut ret = std::path::PathBuf::new();
+        for part in permalink.split('/') {
+            match part {
+                "" | "." => {},
+                ".." => {
+                    return Err(format!("permalink {:?} escapes the output directory", permalink));
+                },
+                part => ret.push(part)
+            }
+        }
+        if permalink.ends_with('/') || ret.as_os_str().is_empty() {
+            ret.push("index.html");
+        }
+        return Ok(ret);
+    }
+    let (language, relative) = i18n::split(&config.i18n, source.strip_prefix(&config.source).unwrap(), rendered);
+    let pretty = config.pretty_urls && rendered && matches!(relative.extension().and_then(|e| e.to_str()), Some("html") | Some("htm") | Some("md"))
+        && relative.file_stem().is_some_and(|stem| stem != "404");
+    if !pretty {
+        return Ok(i18n::prefix(&config.i18n, &language, relative));
+    }
+    let mut ret = relative.parent().unwrap().to_path_buf();
+    let stem = relative.file_stem().unwrap();
+    if stem != "index" {
+        ret.push(stem);
+    }
+    ret.push("index.html");
+    Ok(i18n::prefix(&config.i18n, &language, ret))
+}
+
+
+impl Build {
+    fn add_output(&mut self, path : std::path::PathBuf, source : std::path::PathBuf, output : Output) {
+        if let Some((other, _)) = self.outputs.get(&path) {
+            self.errors.push(format!("{} and {} would both be written to {}", other.display(), source.display(), path.display()));
+            return;
+        }
+        self.outputs.insert(path, (source, output));
+    }
+
+    fn warn(&mut self, message : String) {
+        if self.deny_warnings {
+            self.denied.push(message);
+        }
+        else {
+            log::warn(&message);
+            self.warnings += 1;
+        }
+    }
+
+    fn take_warnings(&mut self, source : &std::path::Path, warnings : &std::cell::RefCell<Vec<String>>) { // the ones functions ran into while a page rendered
+        for message in warnings.take() {
+            self.warn(format!("{}: {}", source.display(), message));
+        }
+    }
+
+    fn lint(&mut self, source : &std::path::Path, allowed : &[String], warnings : Vec<rasta::Warning>) {
+        for message in self.linter.filter(source, allowed, warnings) {
+            self.warn(message);
+        }
+    }
+
+    fn report(&self, started : std::time::Instant) { // the summary at the end of every build
+        let rendered = self.outputs.values().filter(|(_, output)| matches!(output, Output::Rendered (_))).count();
+        let copied = self.outputs.len() - rendered;
+        let failed = !self.errors.is_empty() || !self.denied.is_empty();
+        let generated = rendered - self.pages.len().min(rendered);
+        let (warnings, errors) = (self.warnings + self.denied.len(), self.errors.len() + self.denied.len());
+        let message = format!("Build {} in {:.2}s: {} pages rendered, {} other files generated, {} copied, {} skipped; {} warnings, {} errors.",
+            if failed { "failed" } else { "finished" }, started.elapsed().as_secs_f64(), self.pages.len(), generated, copied, self.skipped, warnings, errors);
+        log::event(if failed { log::Level::Error } else { log::Level::Info }, "summary", &message, serde_json::json!({
+            "status" : if failed { "failed" } else { "finished" },
+            "pages" : self.pages.len(),
+            "generated" : generated,
+            "copied" : copied,
+            "skipped" : self.skipped,
+            "warnings" : warnings,
+            "errors" : errors,
+            "duration_ms" : started.elapsed().as_millis() as u64
+        }));
+    }
+
+    fn problems(&self) -> (usize, usize, usize) { // (warnings, errors, skipped) so far, to see what rendering something added
+        (self.warnings + self.denied.len(), self.errors.len(), self.skipped)
+    }
+
+    fn page_event(&self, before : (usize, usize, usize), started : std::time::Instant, source : &std::path::Path, output : Option<&std::path::Path>, template : Option<&str>) {
+        // one event for every page rendered (or not), for build dashboards
+        let (warnings, errors, skipped) = self.problems();
+        let status = if errors > before.1 { "failed" } else if skipped > before.2 { "skipped" } else { "rendered" };
+        let message = match (status, output) {
+            ("rendered", Some(output)) => format!(" Rendered {} to {}", source.display(), output.display()),
+            _ => format!(" {} {}", if status == "failed" { "Failed to render" } else { "Skipped" }, source.display())
+        };
+        log::event(log::Level::Info, "page", &message, serde_json::json!({
+            "source" : source.display().to_string(),
+            "output" : output.map(|o| o.display().to_string()),
+            "template" : template,
+            "status" : status,
+            "warnings" : warnings - before.0,
+            "duration_ms" : started.elapsed().as_micros() as f64 / 1000.0
+        }));
+    }
+
+    fn copy_asset(&mut self, config : &config::Config, path_propre : std::path::PathBuf) {
+        match output_path(config, &path_propre, false, None) {
+            Ok(path) => self.add_output(path, path_propre, Output::Copied),
+            Err(e) => self.errors.push(format!("{}: {}", path_propre.display(), e))
+        }
+    }
 }
 
 
-fn parse_all_recursive(templates : &Vec<(String, rasta::TreeNode)>, rpath : std::path::PathBuf) {
-    for path in std::fs::read_dir(rpath).unwrap() {
-        let path_propre = path.as_ref().unwrap().path();
-        let dirname = path_propre.file_name().unwrap();
-        if dirname == "_templates" || dirname == "output" {
+fn collect_sources(rules : &ignore::IgnoreRules, skip : &[std::path::PathBuf], rpath : std::path::PathBuf, sources : &mut Vec<std::path::PathBuf>) -> Result<(), std::io::Error> {
+    for path in std::fs::read_dir(rpath)? {
+        let path_propre = path?.path();
+        if let Ok(canonical) = path_propre.canonicalize() {
+            if skip.contains(&canonical) { // never render the output or templates directories, wherever they are
+                continue;
+            }
+        }
+        let meta = std::fs::metadata(&path_propre)?;
+        if rules.is_ignored(&path_propre, meta.is_dir()) {
             continue;
         }
-        let meta = std::fs::metadata(&path_propre).unwrap();
         if meta.is_dir() {
-            parse_all_recursive(templates, path_propre);
+            collect_sources(rules, skip, path_propre, sources)?;
         }
         else if meta.is_file() {
-            println!(" Rendering {}", (&path_propre).display());
-            let r = rasta::TreeNode::parse(path_propre.clone());
-            let text = match r {
-                Ok(r) => {
-                    let string = if r.is_plaintext() {
-                        r.plaintext()
-                    }
-                    else {
-                        let sacrifice = rasta::Scope::top().wrap();
-                        let content = rasta::Scope::chitlin_w(sacrifice.clone(), "content".to_string());
-                        r.render(content); // toss the render result, we only want to fill the scope. clean this up later.
-                        let template_name = match sacrifice.borrow().get("content.template".to_string()) {
-                            Some(value) => value,
-                            None => "default".to_string()
-                        };
-                        println!("  Parsing with template {}", template_name);
-                        let mut template : Option<usize> = None;
-                        for (index, pair) in templates.iter().enumerate() {
-                            if pair.0 == template_name {
-                                template = Some(index);
-                            }
-                        }
-                        if template.is_none() {
-                            println!("   Invalid template");
-                            continue;
-                        }
-                        let template = template.unwrap();
-                        templates[template].1.render(sacrifice)
-                    };
-                    string.into_bytes()
-                },
-                Err(_) => {
-                    std::fs::read(&path_propre).unwrap()
-                }
-            };
-            //sacrifice.borrow().draw_tree(0);
-            let mut path = std::path::PathBuf::from("output");
-            path.push(path_propre);
-            println!("creating {:?}", path);
-            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
-            let mut file = std::fs::File::create(path).unwrap();
-            
-            file.write_all(&text).unwrap();
+            sources.push(path_propre);
         }
         else {
-            println!("WARNING: Your filesystem tree looks kinda ill. Consider, perhaps, cleaning it up?");
+            log::warn(&format!("{} isn't a file or a directory, so it's been left out", path_propre.display()));
+        }
+    }
+    Ok(())
+}
+
+
+fn template_paths(config : &config::Config) -> Result<Vec<std::path::PathBuf>, std::io::Error> { // the files directly in the templates directory, minus whatever the source tree would ignore
+    let mut rules = ignore::IgnoreRules::load(config.templates.clone())?;
+    for pattern in &config.ignore {
+        rules.add_ignore(pattern);
+    }
+    for pattern in &config.include {
+        rules.add_include(pattern);
+    }
+    let mut paths = vec![];
+    for entry in std::fs::read_dir(&config.templates)? {
+        let path = entry?.path();
+        if !std::fs::metadata(&path)?.is_file() { // partials/ and the like; templates are only looked up by their own name
+            log::debug(&format!("{} isn't a file, so it isn't a template", path.display()));
+            continue;
+        }
+        if !rules.is_ignored(&path, false) {
+            paths.push(path);
         }
     }
+    paths.sort();
+    Ok(paths)
+}
+
+
+struct Site<'a> { // everything that's the same for every page
+    config : &'a config::Config,
+    templates : &'a [(String, rasta::TreeNode)],
+    functions : &'a [(&'a str, rasta::Function)],
+    taxonomies : &'a [taxonomy::Taxonomy],
+    strings : std::rc::Rc<i18n::Strings>,
+    translations : &'a i18n::Translations,
+    warnings : std::rc::Rc<std::cell::RefCell<Vec<String>>> // what functions complained about, since they can't get at the Build
 }
 
 
-fn main() {
-    println!("** Sitix v{} by Tyler Clarke **", env!("CARGO_PKG_VERSION"));
-    let mut args = SitixArgs::parse();
-    if args.directory.is_none() {
-        args.directory = Some(std::path::PathBuf::from("."));
+impl Site<'_> {
+    fn scope(&self, language : &str) -> std::rc::Rc<std::cell::RefCell<rasta::Scope>> {
+        // A fresh template scope, for rendering a page into (as its content child) and then rendering the layout in.
+        // Its parent is the site scope, and the site scope's parent is the global one, which has the functions.
+        let top = rasta::Scope::top().wrap();
+        for (name, function) in self.functions {
+            top.borrow_mut().define(name, function.clone());
+        }
+        let strings = self.strings.clone();
+        let warnings = self.warnings.clone();
+        let lang = language.to_string();
+        let [.., translate] = FUNCTIONS;
+        top.borrow_mut().define(translate, std::rc::Rc::new(move |args : &str, _| {
+            let key = args.trim();
+            strings.translate(&lang, key).unwrap_or_else(|| {
+                warnings.borrow_mut().push(format!("no {} translation for {}", lang, key));
+                key.to_string()
+            })
+        }));
+        let site = rasta::Scope::chitlin_kind_w(top, "site".to_string(), rasta::ScopeKind::Site);
+        self.config.fill_scope(site.clone());
+        taxonomy::fill_scope(self.taxonomies, language, site.clone());
+        rasta::Scope::block_w(site, rasta::ScopeKind::Template)
     }
-    if args.output_directory.is_none() {
-        args.output_directory = Some(args.directory.as_ref().unwrap().clone());
-        args.output_directory.as_mut().unwrap().push("output");
+
+    fn template(&self, name : &str) -> Option<&rasta::TreeNode> {
+        self.templates.iter().find(|pair| pair.0 == name).map(|pair| &pair.1)
     }
-    let mut templates_dir = args.directory.unwrap().clone();
-    templates_dir.push("_templates");
-    println!("Checking project validity.");
-    if templates_dir.exists() {
-        println!(" Templates directory exists; project is valid.");
+}
+
+
+struct Meta { // what's known about a page before anything is rendered. Working this out for every page is the first phase of a build.
+    template : String,
+    language : String,
+    key : std::path::PathBuf, // the source path with the language taken out, for finding translations
+    path : Result<std::path::PathBuf, String> // in the output directory
+}
+
+
+fn metadata(config : &config::Config, path_propre : &std::path::Path, tree : &rasta::TreeNode) -> Meta {
+    // This reads assignments straight out of the tree without rendering it, so template and permalink have to be written out literally.
+    let rendered = !tree.is_plaintext();
+    let (language, key) = i18n::split(&config.i18n, path_propre.strip_prefix(&config.source).unwrap(), rendered);
+    Meta {
+        template : tree.assignment("template").map(|t| t.trim().to_string()).unwrap_or_else(|| config.default_template.clone()),
+        language,
+        key,
+        path : output_path(config, path_propre, rendered, tree.assignment("permalink"))
+    }
+}
+
+
+fn render_page(site : &Site, path_propre : std::path::PathBuf, r : &rasta::TreeNode, meta : Meta, build : &mut Build) {
+    // The second and third phases: render the page's content, once, and then render its layout around it. The layout gets a block of its
+    // own, so it can read the page (as content or page) but can't change anything in it.
+    let config = site.config;
+    log::debug(&format!(" Rendering {}", path_propre.display()));
+    let path = match meta.path {
+        Ok(path) => path,
+        Err(e) => {
+            build.errors.push(format!("{}: {}", path_propre.display(), e));
+            return;
+        }
+    };
+    let mut sitemap = true;
+    let mut title = None;
+    let mut date = None;
+    let mut summary = None;
+    let mut body = String::new();
+    let rendered = !r.is_plaintext();
+    let mut text = if r.is_plaintext() {
+        r.plaintext()
     }
     else {
-        println!(" Templates directory does not exist. Exiting.");
-        return;
+        let template = match site.template(&meta.template) {
+            Some(template) => template,
+            None => {
+                build.errors.push(format!("{}: there's no template called {}, so this page can't be rendered", path_propre.display(), meta.template));
+                return;
+            }
+        };
+        let scope = site.scope(&meta.language);
+        let content = rasta::Scope::chitlin_kind_w(scope.clone(), "content".to_string(), rasta::ScopeKind::Page);
+        rasta::Scope::set_w(content.clone(), "lang", meta.language);
+        rasta::Scope::set_w(content.clone(), "url", sitemap::page_url("", &path));
+        site.translations.fill_scope(&meta.key, content.clone());
+        body = r.render(content.clone()); // the layout gets this through the scope; we keep a copy for feeds.
+        let page = content.borrow();
+        sitemap = sitemap::listed(page.get("page.sitemap".to_string()));
+        title = page.get("page.title".to_string());
+        summary = page.get("page.summary".to_string());
+        date = match page.get("page.date".to_string()) {
+            Some(text) => match dates::parse(&text) {
+                Some(date) => Some(date),
+                None => {
+                    build.warn(format!("{}: can't understand the date {:?}; use something like 2024-03-01 or 2024-03-01T12:30:00Z", path_propre.display(), text));
+                    None
+                }
+            },
+            None => None
+        };
+        log::trace(&format!("  Parsing with template {}", meta.template));
+        let text = template.render(rasta::Scope::block_w(scope.clone(), rasta::ScopeKind::Block));
+        let mut warnings = scope.borrow().take_warnings();
+        warnings.extend(lint::unused(r, &[template], &site.config.taxonomies.iter().map(|t| t.name.clone()).collect::<Vec<_>>()));
+        let mut allowed = lint::allowed(r);
+        allowed.extend(lint::allowed(template));
+        build.lint(&path_propre, &allowed, warnings);
+        build.take_warnings(&path_propre, &site.warnings);
+        text
+    };
+    let is_html = matches!(path.extension().and_then(|e| e.to_str()), Some("html") | Some("htm"));
+    if config.minify_html && rendered && is_html {
+        text = minify::minify_html(&text);
     }
-    println!("Cleaning output directory.");
-    println!(" Removing old output directory.");
-    if args.output_directory.as_ref().unwrap().exists() {
-        std::fs::remove_dir_all(args.output_directory.as_ref().unwrap()).expect("Failed to clean output directory.");
-        println!("  Successfully removed old output directory.");
+    if rendered && is_html {
+        build.pages.push(Page {
+            source : path_propre.strip_prefix(&config.source).unwrap().to_path_buf(),
+            path : path.clone(),
+            sitemap,
+            modified : std::fs::metadata(&path_propre).and_then(|m| m.modified()).unwrap_or(std::time::SystemTime::now()),
+            title,
+            date,
+            summary,
+            content : body
+        });
+    }
+    build.add_output(path, path_propre, Output::Rendered(text.into_bytes()));
+}
+
+
+fn term_pages<'a>(pages : &'a [Page], term : &taxonomy::Term) -> (Vec<&'a Page>, std::time::SystemTime) {
+    // the pages with a term, newest first, and when the newest of them changed (so the listing's lastmod only moves when a page in it does)
+    let mut ret : Vec<&Page> = pages.iter().filter(|page| term.sources.contains(&page.source)).collect();
+    ret.sort_by(|a, b| b.date.cmp(&a.date).then(a.path.cmp(&b.path)));
+    let modified = ret.iter().map(|page| page.modified).max().unwrap_or(std::time::UNIX_EPOCH);
+    (ret, modified)
+}
+
+
+fn render_taxonomies(site : &Site, build : &mut Build) { // one listing page per term, for every taxonomy that has a template
+    for taxonomy in site.taxonomies {
+        let template = match site.template(&taxonomy.settings.template) {
+            Some(template) => template,
+            None => {
+                if !taxonomy.terms.is_empty() {
+                    log::debug(&format!("No {} template, so there are no listing pages for {}.", taxonomy.settings.template, taxonomy.settings.name));
+                }
+                continue;
+            }
+        };
+        log::info(&format!("Rendering {} listing pages", taxonomy.settings.name));
+        for term in taxonomy.terms.values() {
+            let (started, before) = (std::time::Instant::now(), build.problems());
+            let scope = site.scope(&term.language);
+            rasta::Scope::chitlin_kind_w(scope.clone(), "content".to_string(), rasta::ScopeKind::Page);
+            taxonomy::fill_term(taxonomy, term, scope.clone(), "term");
+            let (pages, modified) = term_pages(&build.pages, term);
+            for (index, page) in pages.iter().enumerate() {
+                let prefix = format!("term.pages.{}", index);
+                rasta::Scope::set_w(scope.clone(), &format!("{}.url", prefix), sitemap::page_url("", &page.path));
+                rasta::Scope::set_w(scope.clone(), &format!("{}.title", prefix), page.title.clone().unwrap_or_else(|| page.path.display().to_string()));
+                if let Some(date) = page.date {
+                    rasta::Scope::set_w(scope.clone(), &format!("{}.date", prefix), dates::w3c_date(date));
+                }
+                if let Some(summary) = &page.summary {
+                    rasta::Scope::set_w(scope.clone(), &format!("{}.summary", prefix), summary.clone());
+                }
+            }
+            rasta::Scope::set_w(scope.clone(), "content.title", term.name.clone());
+            rasta::Scope::set_w(scope.clone(), "content.lang", term.language.clone());
+            let mut text = template.render(rasta::Scope::block_w(scope.clone(), rasta::ScopeKind::Block));
+            let source = std::path::PathBuf::from(format!("[{} {}]", taxonomy.settings.name, term.name));
+            build.lint(&source, &lint::allowed(template), scope.borrow().take_warnings());
+            build.take_warnings(&source, &site.warnings);
+            if site.config.minify_html {
+                text = minify::minify_html(&text);
+            }
+            let path = term.path.clone();
+            build.pages.push(Page {
+                source : site.config.templates.join(&taxonomy.settings.template),
+                path : path.clone(),
+                sitemap : true,
+                modified,
+                title : Some(term.name.clone()),
+                date : None,
+                summary : None,
+                content : String::new()
+            });
+            build.add_output(path.clone(), source.clone(), Output::Rendered(text.into_bytes()));
+            build.page_event(before, started, &source, Some(&path), Some(&taxonomy.settings.template));
+        }
+    }
+}
+
+
+fn main() -> std::process::ExitCode {
+    let args = SitixArgs::parse();
+    match args.command {
+        Some(Command::Build (build_args)) => build(build_args),
+        Some(Command::Fmt (fmt_args)) => format(fmt_args),
+        Some(Command::Lsp) => {
+            log::init(log::Level::Warn, log::Format::Human); // stdout belongs to the editor
+            match lsp::run() {
+                Ok(()) => std::process::ExitCode::SUCCESS,
+                Err(e) => {
+                    log::error(&format!("Language server failed: {}", e));
+                    std::process::ExitCode::from(EXIT_FAILED)
+                }
+            }
+        },
+        None => build(args.build)
+    }
+}
+
+
+fn build(args : BuildArgs) -> std::process::ExitCode {
+    let started = std::time::Instant::now();
+    args.log.init();
+    log::info(&format!("** Sitix v{} by Tyler Clarke **", env!("CARGO_PKG_VERSION")));
+    let directory = args.directory.unwrap_or(std::path::PathBuf::from("."));
+    log::info("Loading configuration.");
+    let mut config = match config::Config::load(&directory) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error(&format!("Invalid configuration: {}. Exiting.", e));
+            return std::process::ExitCode::from(EXIT_FAILED);
+        }
+    };
+    if let Some(output) = args.output_directory {
+        config.output = output;
+    }
+    if let Some(source) = args.source {
+        config.source = source;
+    }
+    if let Some(templates) = args.templates {
+        config.templates = templates;
+    }
+    if let Some(default_template) = args.default_template {
+        config.default_template = default_template;
+    }
+    if args.base_url.is_some() {
+        config.base_url = args.base_url;
+    }
+    if args.title.is_some() {
+        config.title = args.title;
+    }
+    if args.pretty_urls {
+        config.pretty_urls = true;
+    }
+    if args.minify {
+        config.minify_html = true;
+    }
+    let strings = match i18n::Strings::load(&config.i18n) {
+        Ok(strings) => strings,
+        Err(e) => {
+            log::error(&format!("Invalid translation strings: {}. Exiting.", e));
+            return std::process::ExitCode::from(EXIT_FAILED);
+        }
+    };
+    log::debug("Checking project validity.");
+    if config.templates.exists() {
+        log::trace(" Templates directory exists; project is valid.");
     }
     else {
-        println!("  Nothing to do! Selected output dir does not exist.");
+        log::error(&format!("Templates directory {} does not exist. Exiting.", config.templates.display()));
+        return std::process::ExitCode::from(EXIT_FAILED);
     }
-    println!(" Creating empty output directory.");
-    std::fs::create_dir_all(args.output_directory.as_ref().unwrap()).expect("Failed to create output directory.");
-    println!("  Successfully created new empty output direcory.");
-    println!("Output clean successful.");
-    println!("Creating template list");
+    log::debug("Checking the output directory.");
+    let previous = match output::check(&config.output, &[&config.source, &directory, &config.templates, &config.i18n.strings]) {
+        Ok(previous) => previous,
+        Err(e) => {
+            log::error(&format!("Refusing to clean the output directory: {}. Exiting.", e));
+            return std::process::ExitCode::from(EXIT_FAILED);
+        }
+    };
+    log::debug("Creating template list");
     //let mut templates : Vec <rasta::RastaTemplate> = Vec::new();
     let mut templates : Vec<(String, rasta::TreeNode)> = vec![];
-    for path in std::fs::read_dir(templates_dir).unwrap() {
-        /*let mut file = match std::fs::File::open(path.as_ref().unwrap().path()) {
-            Ok(f) => f,
-            Err(_) => {continue;}
+    let template_paths = match template_paths(&config) {
+        Ok(paths) => paths,
+        Err(e) => {
+            log::error(&format!("Can't read the templates directory: {}. Exiting.", e));
+            return std::process::ExitCode::from(EXIT_FAILED);
+        }
+    };
+    for path in template_paths {
+        match rasta::TreeNode::parse(path.clone()) {
+            Ok(r) => templates.push((path.file_stem().unwrap().to_string_lossy().to_string(), r)),
+            Err(e) => {
+                log::error(&format!("Template {} is broken: {}. Exiting.", path.display(), e));
+                return std::process::ExitCode::from(EXIT_FAILED);
+            }
+        }
+    }
+    log::debug("Loading ignore rules");
+    let mut rules = match ignore::IgnoreRules::load(config.source.clone()) {
+        Ok(rules) => rules,
+        Err(e) => {
+            log::error(&format!("Failed to read .sitixignore: {}. Exiting.", e));
+            return std::process::ExitCode::from(EXIT_FAILED);
+        }
+    };
+    for pattern in &config.ignore {
+        rules.add_ignore(pattern);
+    }
+    for pattern in &config.include {
+        rules.add_include(pattern);
+    }
+    let skip : Vec<std::path::PathBuf> = [&config.output, &config.templates, &config.images.cache, &config.i18n.strings, &directory.join("sitix.toml")].iter().filter_map(|p| p.canonicalize().ok()).collect();
+    let mut sources = vec![];
+    if let Err(e) = collect_sources(&rules, &skip, config.source.clone(), &mut sources) {
+        log::error(&format!("Failed to read the source directory: {}. Exiting.", e));
+        return std::process::ExitCode::from(EXIT_FAILED);
+    }
+    sources.sort(); // read_dir order is up to the filesystem; builds should come out the same every time
+    let (assets, pages) : (Vec<_>, Vec<_>) = sources.into_iter().partition(|path| is_asset(&config, path));
+    let mut build = Build {
+        deny_warnings : args.deny_warnings,
+        linter : lint::Linter {
+            allow : config.lint.allow.clone()
+        },
+        ..Default::default()
+    };
+    for (name, template) in &templates {
+        build.lint(&config.templates.join(name), &lint::allowed(template), lint::unused(template, &[], &[]));
+    }
+    log::info("Processing assets");
+    let mut asset_map = assets::AssetMap::default();
+    for path_propre in assets {
+        let path = match output_path(&config, &path_propre, false, None) {
+            Ok(path) => path,
+            Err(e) => {
+                build.errors.push(format!("{}: {}", path_propre.display(), e));
+                continue;
+            }
+        };
+        match assets::process(&config.assets, &path_propre, &path) {
+            Ok(assets::Asset::Copy) => {
+                log::debug(&format!(" Copying {}", path_propre.display()));
+                build.add_output(path, path_propre, Output::Copied);
+            },
+            Ok(assets::Asset::Partial) => {
+                log::debug(&format!(" Skipping partial {}", path_propre.display()));
+                build.skipped += 1;
+            },
+            Ok(assets::Asset::Built (built, data)) => {
+                log::debug(&format!(" Building {}", path_propre.display()));
+                asset_map.insert(&path, &built);
+                build.add_output(built, path_propre, Output::Rendered(data));
+            },
+            Err(e) => build.errors.push(format!("{}: {}", path_propre.display(), e))
+        }
+    }
+    let asset_map = std::rc::Rc::new(asset_map);
+    let images = std::rc::Rc::new(std::cell::RefCell::new(images::Images::new(&config)));
+    let images_fn = images.clone();
+    let highlighter = std::rc::Rc::new(std::cell::RefCell::new(highlight::Highlighter::default()));
+    let highlighter_fn = highlighter.clone();
+    let warnings = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+    let (image_warnings, highlight_warnings) = (warnings.clone(), warnings.clone());
+    let [asset_url, image, highlight, _] = FUNCTIONS; // t is different for every language, so Site::scope defines it
+    let functions : Vec<(&str, rasta::Function)> = vec![
+        (asset_url, std::rc::Rc::new(move |args : &str, _| asset_map.url(args))),
+        (image, std::rc::Rc::new(move |args : &str, _| {
+            match images_fn.borrow_mut().attributes(args) {
+                Ok(attributes) => attributes,
+                Err(e) => {
+                    image_warnings.borrow_mut().push(format!("could not process image {}: {}", args.trim(), e));
+                    format!("src=\"{}\"", args.trim())
+                }
+            }
+        })),
+        (highlight, std::rc::Rc::new(move |args : &str, body : Option<String>| {
+            let body = body.unwrap_or_default();
+            match highlighter_fn.borrow_mut().highlight(args, &body) {
+                Ok(html) => html,
+                Err(e) => {
+                    highlight_warnings.borrow_mut().push(format!("could not highlight {} code: {}", args.trim(), e));
+                    body
+                }
+            }
+        }))
+    ];
+    log::info("Parsing");
+    let mut parsed = vec![];
+    for path_propre in pages {
+        match rasta::TreeNode::parse(path_propre.clone()) {
+            Ok(tree) => parsed.push((path_propre, tree)),
+            Err(e) if e.is::<std::io::Error>() => { // it isn't text, or something like that
+                log::warn(&format!("could not read {} as Rasta ({}); copying it instead", path_propre.display(), e));
+                build.copy_asset(&config, path_propre);
+            },
+            Err(e) => build.errors.push(format!("{}: {}", path_propre.display(), e))
+        }
+    }
+    let (taxonomies, clashes) = taxonomy::collect(&config.taxonomies, &config.i18n, &parsed.iter().map(|(path, tree)| {
+        let relative = path.strip_prefix(&config.source).unwrap().to_path_buf();
+        let language = i18n::split(&config.i18n, &relative, true).0;
+        (relative, language, tree)
+    }).collect::<Vec<_>>());
+    for clash in clashes {
+        build.warn(clash);
+    }
+    let metas : Vec<Meta> = parsed.iter().map(|(path_propre, tree)| metadata(&config, path_propre, tree)).collect();
+    let mut translations = i18n::Translations::default();
+    if !config.i18n.languages.is_empty() {
+        for meta in &metas {
+            if let Ok(path) = &meta.path { // errors get reported when the page renders
+                translations.add(meta.key.clone(), meta.language.clone(), sitemap::page_url(config.base_url.as_deref().unwrap_or(""), path));
+            }
+        }
+    }
+    let site = Site {
+        config : &config,
+        templates : &templates,
+        functions : &functions,
+        taxonomies : &taxonomies,
+        strings : std::rc::Rc::new(strings),
+        translations : &translations,
+        warnings
+    };
+    log::info("Rendering");
+    for ((path_propre, tree), meta) in parsed.into_iter().zip(metas) {
+        let (started, before) = (std::time::Instant::now(), build.problems());
+        let (source, output, template) = (path_propre.clone(), meta.path.clone().ok(), (!tree.is_plaintext()).then(|| meta.template.clone()));
+        render_page(&site, path_propre, &tree, meta, &mut build);
+        build.page_event(before, started, &source, output.as_deref(), template.as_deref());
+    }
+    render_taxonomies(&site, &mut build);
+    for (path, cached) in images.borrow().generated() {
+        build.add_output(path.clone(), cached.clone(), Output::Copied);
+    }
+    if highlighter.borrow().used {
+        match highlight::stylesheet(&config.highlight.theme) {
+            Ok(css) => build.add_output(config.highlight.stylesheet.clone(), std::path::PathBuf::from("[highlight] stylesheet"), Output::Rendered(css.into_bytes())),
+            Err(e) => build.errors.push(e.to_string())
+        }
+    }
+    if config.sitemap {
+        match &config.base_url {
+            Some(base_url) => {
+                let mut pages : Vec<(std::path::PathBuf, i64, bool)> = build.pages.iter().map(|page| (page.path.clone(), dates::unix_seconds(page.modified), page.sitemap)).collect();
+                pages.sort();
+                for (path, xml) in sitemap::build(base_url, &pages, sitemap::MAX_URLS) {
+                    build.add_output(path, std::path::PathBuf::from("[sitemap]"), Output::Rendered(xml.into_bytes()));
+                }
+            },
+            None => {
+                log::info("No base_url configured, so there's no sitemap.");
+            }
+        }
+    }
+    for feed in &config.feeds {
+        let base_url = match &config.base_url {
+            Some(base_url) => base_url,
+            None => {
+                build.errors.push(format!("the feed for {} needs base_url to be set", feed.collection.display()));
+                continue;
+            }
+        };
+        let mut entries : Vec<feeds::Entry> = build.pages.iter().filter(|page| page.source.starts_with(&feed.collection) && page.date.is_some()).map(|page| feeds::Entry {
+            url : sitemap::page_url(base_url, &page.path),
+            title : page.title.clone().unwrap_or_else(|| page.path.display().to_string()),
+            date : page.date.unwrap(),
+            summary : page.summary.clone(),
+            content : page.content.clone()
+        }).collect();
+        entries.sort_by(|a, b| b.date.cmp(&a.date).then(a.url.cmp(&b.url)));
+        if feed.limit > 0 {
+            entries.truncate(feed.limit);
+        }
+        let title = feed.title.as_ref().or(config.title.as_ref()).cloned().unwrap_or_else(|| base_url.split("://").last().unwrap_or_default().trim_end_matches('/').to_string());
+        log::info(&format!("Writing feeds for {} ({} entries)", feed.collection.display(), entries.len()));
+        if let Some(path) = &feed.rss {
+            let xml = feeds::rss(feed, &title, base_url, &sitemap::page_url(base_url, path), &entries);
+            build.add_output(path.clone(), std::path::PathBuf::from("[feeds]"), Output::Rendered(xml.into_bytes()));
+        }
+        if let Some(path) = &feed.atom {
+            let xml = feeds::atom(feed, &title, base_url, &sitemap::page_url(base_url, path), &entries);
+            build.add_output(path.clone(), std::path::PathBuf::from("[feeds]"), Output::Rendered(xml.into_bytes()));
+        }
+    }
+    log::info("Checking links");
+    let html : Vec<(std::path::PathBuf, std::path::PathBuf, &str)> = build.outputs.iter().filter_map(|(path, (source, output))| match output {
+        Output::Rendered (data) if matches!(path.extension().and_then(|e| e.to_str()), Some("html") | Some("htm")) => {
+            std::str::from_utf8(data).ok().map(|text| (path.clone(), source.clone(), text))
+        },
+        _ => None
+    }).collect();
+    let problems = links::check(config.base_url.as_deref(), &html, &|path| build.outputs.contains_key(path));
+    if problems.is_empty() {
+        log::debug(" No broken links.");
+    }
+    for problem in problems {
+        if args.strict {
+            build.denied.push(problem);
+        }
+        else {
+            build.warn(problem);
+        }
+    }
+    if !build.errors.is_empty() || !build.denied.is_empty() {
+        for error in build.errors.iter().chain(&build.denied) {
+            log::error(error);
+        }
+        build.report(started);
+        return std::process::ExitCode::from(if build.errors.is_empty() { EXIT_DENIED } else { EXIT_FAILED });
+    }
+    if args.diff {
+        log::info("Comparing with the existing output");
+        let paths : Vec<&std::path::Path> = build.outputs.keys().map(|path| path.as_path()).collect();
+        let contents = |path : &std::path::Path| match &build.outputs[path] {
+            (_, Output::Rendered (data)) => Ok(data.clone()),
+            (source, Output::Copied) => std::fs::read(source)
         };
-        //templates.push(rasta::RastaPage::load(&mut file).unwrap());
-        let tokens = rasta::lexer(&mut file);
-        let mut tokens = tokens.iter().peekable();
-        let r = rasta::TreeNode::congeal(&mut tokens);
-        //println!("{}", r.render(rasta::Scope::top().wrap()));*/
-        let r = rasta::TreeNode::parse(path.as_ref().unwrap().path()).unwrap(); // unwrap is safe here, because if your templates directory has invalid Rasta you have bigger problems.
-        templates.push((path.unwrap().path().file_stem().unwrap().to_str().unwrap().to_string(), r));
-    }
-    println!("Rendering");
-    parse_all_recursive(&templates, std::path::PathBuf::from("."));
+        match diff::compare(&config.output, &previous, &paths, &contents) {
+            Ok((differences, unchanged)) => {
+                for difference in &differences {
+                    let message = match &difference.diff {
+                        Some(diff) => format!("{} {}\n{}", difference.change.symbol(), difference.path.display(), diff),
+                        None => format!("{} {} (binary)\n", difference.change.symbol(), difference.path.display())
+                    };
+                    log::result("diff", &message, serde_json::json!({
+                        "path" : difference.path.display().to_string(),
+                        "change" : difference.change.name(),
+                        "diff" : difference.diff
+                    }));
+                }
+                let count = |change| differences.iter().filter(|difference| difference.change == change).count();
+                log::info(&format!("{} added, {} removed, {} changed, {} unchanged", count(diff::Change::Added), count(diff::Change::Removed), count(diff::Change::Changed), unchanged));
+            },
+            Err(e) => {
+                log::error(&format!("Failed to read the existing output: {}", e));
+                build.errors.push(format!("{}: {}", config.output.display(), e));
+                build.report(started);
+                return std::process::ExitCode::from(EXIT_FAILED);
+            }
+        }
+    }
+    if args.dry_run {
+        log::info("Dry run, so nothing was written.");
+        build.report(started);
+        return std::process::ExitCode::SUCCESS;
+    }
+    log::debug(&format!("Cleaning output directory ({} files from the last build).", previous.len()));
+    let cleaned = output::clean(&config.output, &previous).and_then(|_| std::fs::create_dir_all(&config.output))
+        .and_then(|_| output::write_manifest(&config.output, &build.outputs.keys().map(|path| path.as_path()).collect::<Vec<_>>()));
+    if let Err(e) = cleaned {
+        log::error(&format!("Failed to clean output directory: {}", e));
+        build.errors.push(format!("{}: {}", config.output.display(), e));
+        build.report(started);
+        return std::process::ExitCode::from(EXIT_FAILED);
+    }
+    log::info("Writing output");
+    let mut failures = vec![];
+    for (path, (source, output)) in &build.outputs {
+        let path = config.output.join(path);
+        log::trace(&format!(" Writing {}", path.display()));
+        if let Err(e) = write_output(&path, source, output) {
+            failures.push(format!("{}: {}", path.display(), e));
+        }
+    }
+    if !failures.is_empty() {
+        for failure in &failures {
+            log::error(failure);
+        }
+        build.errors.extend(failures);
+        build.report(started);
+        return std::process::ExitCode::from(EXIT_FAILED);
+    }
+    build.report(started);
+    std::process::ExitCode::SUCCESS
+}
+
+
+fn format(args : FmtArgs) -> std::process::ExitCode {
+    args.log.init();
+    let mut files = vec![];
+    let collected = if args.paths.is_empty() { // everything sitix would render
+        config::Config::load(std::path::Path::new(".")).map_err(|e| e.to_string()).and_then(|config| {
+            let rules = ignore::IgnoreRules::load(config.source.clone()).map_err(|e| e.to_string())?;
+            let skip : Vec<std::path::PathBuf> = [&config.output, &config.templates, &config.images.cache, &config.i18n.strings].iter().filter_map(|p| p.canonicalize().ok()).collect();
+            collect_sources(&rules, &skip, config.source.clone(), &mut files).map_err(|e| e.to_string())?;
+            collect_sources(&ignore::IgnoreRules::new(config.templates.clone()), &[], config.templates.clone(), &mut files).map_err(|e| e.to_string())
+        })
+    }
+    else {
+        args.paths.iter().try_for_each(|path| if path.is_dir() {
+            collect_sources(&ignore::IgnoreRules::new(path.clone()), &[], path.clone(), &mut files)
+        }
+        else {
+            files.push(path.clone());
+            Ok(())
+        }).map_err(|e| e.to_string())
+    };
+    if let Err(e) = collected {
+        log::error(&format!("Can't find the files to format: {}. Exiting.", e));
+        return std::process::ExitCode::from(EXIT_FAILED);
+    }
+    files.sort();
+    let (mut changed, mut failed) = (0, 0);
+    for path in files {
+        if !rasta::has_header(&path).unwrap_or(false) { // not Rasta, so nothing to format
+            log::trace(&format!(" Skipping {}", path.display()));
+            continue;
+        }
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                log::error(&format!("{}: {}", path.display(), e));
+                failed += 1;
+                continue;
+            }
+        };
+        let formatted = match fmt::format(&text) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                log::error(&format!("{}: {}", path.display(), e));
+                failed += 1;
+                continue;
+            }
+        };
+        if formatted == text {
+            log::debug(&format!(" {} is already formatted", path.display()));
+            continue;
+        }
+        changed += 1;
+        if args.check {
+            let diff = diff::unified(&path, &text, &formatted);
+            log::result("fmt", &diff, serde_json::json!({
+                "path" : path.display().to_string(),
+                "diff" : diff
+            }));
+        }
+        else if let Err(e) = std::fs::write(&path, formatted) {
+            log::error(&format!("{}: {}", path.display(), e));
+            failed += 1;
+        }
+        else {
+            log::info(&format!(" Formatted {}", path.display()));
+        }
+    }
+    if args.check && changed > 0 {
+        log::error(&format!("{} files aren't formatted; run sitix fmt", changed));
+    }
+    else {
+        log::info(&format!("{} files {}", changed, if args.check { "need formatting" } else { "formatted" }));
+    }
+    if failed > 0 || (args.check && changed > 0) {
+        return std::process::ExitCode::from(EXIT_FAILED);
+    }
+    std::process::ExitCode::SUCCESS
+}
+
+
+fn write_output(path : &std::path::Path, source : &std::path::Path, output : &Output) -> Result<(), std::io::Error> {
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    match output {
+        Output::Rendered (data) => {
+            let mut file = std::fs::File::create(path)?;
+            file.write_all(data)?;
+        },
+        Output::Copied => {
+            std::fs::copy(source, path)?;
+            let modified = std::fs::metadata(source).and_then(|m| m.modified())?;
+            std::fs::File::options().write(true).open(path)?.set_modified(modified)?; // keep the mtime, so rsync and friends don't think every asset changed
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_paths() {
+        let config = config::Config {
+            source : std::path::PathBuf::from("site"),
+            pretty_urls : true,
+            ..config::Config::default()
+        };
+        let path = |source : &str, rendered : bool, permalink : Option<&str>| output_path(&config, &config.source.join(source), rendered, permalink.map(|p| p.to_string()));
+        assert_eq!(path("about.md", true, None).unwrap(), std::path::PathBuf::from("about/index.html"));
+        assert_eq!(path("blog/post.html", true, None).unwrap(), std::path::PathBuf::from("blog/post/index.html"));
+        assert_eq!(path("sub/index.html", true, None).unwrap(), std::path::PathBuf::from("sub/index.html"));
+        assert_eq!(path("404.html", true, None).unwrap(), std::path::PathBuf::from("404.html"));
+        assert_eq!(path("cat.html", false, None).unwrap(), std::path::PathBuf::from("cat.html")); // copied, not rendered
+        assert_eq!(path("about.md", true, Some("/company/")).unwrap(), std::path::PathBuf::from("company/index.html"));
+        assert_eq!(path("about.md", true, Some("/company/about.html")).unwrap(), std::path::PathBuf::from("company/about.html"));
+        assert_eq!(path("about.md", true, Some("company")).unwrap(), std::path::PathBuf::from("company"));
+        assert_eq!(path("about.md", true, Some("/")).unwrap(), std::path::PathBuf::from("index.html"));
+        assert!(path("about.md", true, Some("/../etc/")).is_err());
+        let plain = config::Config { source : config.source.clone(), ..config::Config::default() };
+        assert_eq!(output_path(&plain, &plain.source.join("about.html"), true, None).unwrap(), std::path::PathBuf::from("about.html"));
+    }
+
+    #[test]
+    fn output_collisions_are_errors() {
+        let mut build = Build::default();
+        build.add_output(std::path::PathBuf::from("about/index.html"), std::path::PathBuf::from("site/about.html"), Output::Copied);
+        build.add_output(std::path::PathBuf::from("about/index.html"), std::path::PathBuf::from("site/about/index.html"), Output::Copied);
+        assert_eq!(build.errors, ["site/about.html and site/about/index.html would both be written to about/index.html"]);
+        assert_eq!(build.outputs[std::path::Path::new("about/index.html")].0, std::path::PathBuf::from("site/about.html"));
+    }
+
+    #[test]
+    fn missing_translations_fall_back_on_the_key() {
+        let directory = std::env::temp_dir().join(format!("sitix-strings-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("en.toml"), "[nav]\nhome = \"Home\"\nabout = \"About\"").unwrap();
+        std::fs::write(directory.join("fr.toml"), "[nav]\nhome = \"Accueil\"").unwrap();
+        let config = config::Config {
+            i18n : config::I18nSettings {
+                languages : vec!["en".to_string(), "fr".to_string()],
+                strings : directory.clone(),
+                ..config::I18nSettings::default()
+            },
+            ..config::Config::default()
+        };
+        let strings = i18n::Strings::load(&config.i18n).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        let site = Site {
+            config : &config,
+            templates : &[],
+            functions : &[],
+            taxonomies : &[],
+            strings : std::rc::Rc::new(strings),
+            translations : &i18n::Translations::default(),
+            warnings : Default::default()
+        };
+        let content = rasta::Scope::chitlin_kind_w(site.scope("fr"), "content".to_string(), rasta::ScopeKind::Page);
+        assert_eq!(rasta::TreeNode::parse_str("[=-][@t nav.home] [@t nav.about] [@t nav.blog][/]").unwrap().render(content), "Accueil About nav.blog");
+        assert_eq!(site.warnings.take(), ["no fr translation for nav.blog"]);
+    }
+
+    #[test]
+    fn term_pages_are_newest_first() {
+        let page = |source : &str, date : Option<i64>, modified : u64| Page {
+            source : std::path::PathBuf::from(source),
+            path : std::path::PathBuf::from(source),
+            sitemap : true,
+            modified : std::time::UNIX_EPOCH + std::time::Duration::from_secs(modified),
+            title : None,
+            date,
+            summary : None,
+            content : String::new()
+        };
+        let pages = [page("old.html", Some(100), 900), page("new.html", Some(200), 500), page("undated.html", None, 700), page("other.html", Some(300), 1000)];
+        let term = taxonomy::Term {
+            name : "Rust".to_string(),
+            slug : "rust".to_string(),
+            language : "en".to_string(),
+            path : std::path::PathBuf::from("tags/rust/index.html"),
+            sources : ["undated.html", "old.html", "new.html"].iter().map(std::path::PathBuf::from).collect()
+        };
+        let (listed, modified) = term_pages(&pages, &term);
+        assert_eq!(listed.iter().map(|page| page.source.to_str().unwrap()).collect::<Vec<_>>(), ["new.html", "old.html", "undated.html"]);
+        assert_eq!(modified, std::time::UNIX_EPOCH + std::time::Duration::from_secs(900));
+        let empty = taxonomy::Term { sources : vec![], ..term };
+        assert_eq!(term_pages(&pages, &empty).1, std::time::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn templates_skip_ignored_files_and_directories() {
+        let directory = std::env::temp_dir().join(format!("sitix-templates-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("partials")).unwrap();
+        for name in ["default.html", ".default.html.swp", "post.html", "draft.html", "partials/nav.html"] {
+            std::fs::write(directory.join(name), "[^content]").unwrap();
+        }
+        let config = config::Config {
+            templates : directory.clone(),
+            ignore : vec!["draft.html".to_string()],
+            ..config::Config::default()
+        };
+        let names : Vec<_> = template_paths(&config).unwrap().iter().map(|path| path.file_name().unwrap().to_string_lossy().to_string()).collect();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(names, ["default.html", "post.html"]);
+    }
 }
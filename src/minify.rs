@@ -0,0 +1,132 @@
+/*  HTML minification, run on rendered pages right before they're written out. Turned on with minify_html = true in sitix.toml, or --minify.
+    It's deliberately conservative: runs of whitespace become a single space (never nothing, since that can glue inline elements
+    together), comments go away (except IE conditional comments), and tags get their insides tidied. The contents of <pre>, <textarea>,
+    <script> and <style> are left exactly as they are.
+*/
+
+
+const RAW_ELEMENTS : &[&str] = &["pre", "textarea", "script", "style"];
+
+
+pub fn minify_html(text : &str) -> String {
+    let chars : Vec<char> = text.chars().collect();
+    let mut ret = String::new();
+    let mut i = 0;
+    let mut pending_space = false;
+    while i < chars.len() {
+        let c = chars[i];
+        if starts_with(&chars, i, "<!--") {
+            if starts_with(&chars, i, "<!--[if") || starts_with(&chars, i, "<!--<![endif]") {
+                let end = find(&chars, i, "-->").map(|e| e + 3).unwrap_or(chars.len());
+                flush_space(&mut ret, &mut pending_space);
+                ret.extend(&chars[i..end]);
+                i = end;
+            }
+            else {
+                i = find(&chars, i + 4, "-->").map(|e| e + 3).unwrap_or(chars.len());
+            }
+            continue;
+        }
+        if c.is_whitespace() {
+            pending_space = true;
+            i += 1;
+            continue;
+        }
+        flush_space(&mut ret, &mut pending_space);
+        if c == '<' && chars.get(i + 1).is_some_and(|n| n.is_ascii_alphabetic() || *n == '/' || *n == '!') {
+            let (tag, end) = read_tag(&chars, i);
+            ret += &tag;
+            i = end;
+            let name : String = tag[1..].chars().take_while(|c| c.is_ascii_alphanumeric()).collect::<String>().to_ascii_lowercase();
+            if RAW_ELEMENTS.contains(&name.as_str()) && !tag.ends_with("/>") {
+                let close = find_ignore_case(&chars, i, &format!("</{}", name)).unwrap_or(chars.len());
+                ret.extend(&chars[i..close]);
+                i = close;
+            }
+            continue;
+        }
+        ret.push(c);
+        i += 1;
+    }
+    ret
+}
+
+
+fn flush_space(ret : &mut String, pending_space : &mut bool) {
+    if *pending_space && !ret.is_empty() {
+        ret.push(' ');
+    }
+    *pending_space = false;
+}
+
+
+fn read_tag(chars : &[char], start : usize) -> (String, usize) { // read a tag starting at the <, collapsing whitespace outside of attribute values
+    let mut ret = String::new();
+    let mut i = start;
+    let mut pending_space = false;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '"' || c == '\'' {
+            let end = chars[i + 1..].iter().position(|q| *q == c).map(|e| i + 1 + e + 1).unwrap_or(chars.len());
+            if ret.ends_with('=') {
+                pending_space = false;
+            }
+            flush_space(&mut ret, &mut pending_space);
+            ret.extend(&chars[i..end]);
+            i = end;
+            continue;
+        }
+        if c.is_whitespace() {
+            pending_space = true;
+            i += 1;
+            continue;
+        }
+        if c == '>' || (c == '/' && chars.get(i + 1) == Some(&'>')) || c == '=' || ret.ends_with('=') {
+            pending_space = false; // no space needed before the end of the tag, or around =
+        }
+        flush_space(&mut ret, &mut pending_space);
+        ret.push(c);
+        i += 1;
+        if c == '>' {
+            break;
+        }
+    }
+    (ret, i)
+}
+
+
+fn starts_with(chars : &[char], at : usize, needle : &str) -> bool {
+    needle.chars().enumerate().all(|(j, n)| chars.get(at + j) == Some(&n))
+}
+
+
+fn find(chars : &[char], from : usize, needle : &str) -> Option<usize> {
+    (from..chars.len()).find(|i| starts_with(chars, *i, needle))
+}
+
+
+fn find_ignore_case(chars : &[char], from : usize, needle : &str) -> Option<usize> {
+    let needle : Vec<char> = needle.chars().collect();
+    (from..chars.len()).find(|i| {
+        needle.iter().enumerate().all(|(j, n)| chars.get(i + j).is_some_and(|c| c.eq_ignore_ascii_case(n)))
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whitespace_and_comments() {
+        assert_eq!(minify_html("<p>\n    a   <b>b</b>\n  c <!-- gone -->\n</p>"), "<p> a <b>b</b> c </p>");
+        assert_eq!(minify_html("<!--[if IE]><p>old</p><![endif]-->"), "<!--[if IE]><p>old</p><![endif]-->");
+    }
+
+    #[test]
+    fn raw_elements_are_kept() {
+        for text in ["<pre>\n  a   b\n</pre>", "<textarea>  x\n  y</textarea>", "<script>let s = `a\n    b`; // <!-- not html -->\n</script>", "<STYLE>\n  p  { }\n</STYLE>"] {
+            assert_eq!(minify_html(&format!("<div>  {}  </div>", text)), format!("<div> {} </div>", text));
+        }
+    }
+}
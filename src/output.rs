@@ -0,0 +1,125 @@
+/*  Looking after the output directory. Sitix only ever removes files it wrote itself: every build leaves a .sitix-manifest in the output
+    directory listing everything it generated, and the next build removes just those before writing the new output. Anything else in
+    there (a CNAME, a .git for GitHub Pages) is left alone.
+    The manifest doubles as a marker. Sitix won't build into a directory that has files in it but no manifest, since that probably isn't
+    an old build, and never into one that contains the source directory, the templates or the project itself (like `sitix . .`).
+*/
+
+use std::path::{Component, Path, PathBuf};
+
+
+pub const MANIFEST : &str = ".sitix-manifest";
+const HEADER : &str = "# Written by sitix: the files it generated here, which the next build will remove. Files not listed are left alone.";
+
+
+pub fn check(output : &Path, protected : &[&Path]) -> Result<Vec<PathBuf>, String> {
+    // make sure it's safe to clean output -> the files the last build generated there (nothing, if there's no output yet)
+    if !output.exists() {
+        return Ok(vec![]);
+    }
+    let canonical = output.canonicalize().map_err(|e| format!("{}: {}", output.display(), e))?;
+    for path in protected {
+        if path.canonicalize().is_ok_and(|path| path.starts_with(&canonical)) {
+            return Err(format!("the output directory {} contains {}; pick another one", output.display(), path.display()));
+        }
+    }
+    let manifest = output.join(MANIFEST);
+    if !manifest.exists() {
+        let empty = std::fs::read_dir(output).map_err(|e| format!("{}: {}", output.display(), e))?.next().is_none();
+        if empty {
+            return Ok(vec![]);
+        }
+        return Err(format!("{} has files in it but no {}, so it doesn't look like sitix output; empty it, pick another output directory, or create an empty {} in it to keep its files", output.display(), MANIFEST, MANIFEST));
+    }
+    let text = std::fs::read_to_string(&manifest).map_err(|e| format!("{}: {}", manifest.display(), e))?;
+    Ok(parse(&text))
+}
+
+
+fn parse(text : &str) -> Vec<PathBuf> { // anything that isn't a plain relative path is skipped, so a bad manifest can't point outside the output directory
+    text.lines().map(|line| line.trim()).filter(|line| !line.is_empty() && !line.starts_with('#')).map(PathBuf::from)
+        .filter(|path| path.components().all(|component| matches!(component, Component::Normal (_)))).collect()
+}
+
+
+pub fn clean(output : &Path, generated : &[PathBuf]) -> Result<(), std::io::Error> { // remove what the last build generated, and any directories that leaves empty
+    let mut directories = vec![];
+    for path in generated {
+        let path = output.join(path);
+        if path.is_file() {
+            std::fs::remove_file(&path)?;
+        }
+        directories.extend(path.ancestors().skip(1).take_while(|directory| *directory != output).map(|directory| directory.to_path_buf()));
+    }
+    directories.sort_by(|a, b| b.components().count().cmp(&a.components().count()).then(a.cmp(b))); // deepest first, so parents are empty by the time they come up
+    directories.dedup();
+    for directory in directories {
+        if std::fs::read_dir(&directory).is_ok_and(|mut entries| entries.next().is_none()) {
+            std::fs::remove_dir(&directory)?;
+        }
+    }
+    Ok(())
+}
+
+
+pub fn write_manifest(output : &Path, generated : &[&Path]) -> Result<(), std::io::Error> {
+    let mut text = String::from(HEADER);
+    text.push('\n');
+    for path in generated {
+        text += &path.components().map(|component| component.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+        text.push('\n');
+    }
+    std::fs::write(output.join(MANIFEST), text)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name : &str) -> PathBuf { // an empty directory of its own for each test
+        let ret = std::env::temp_dir().join(format!("sitix-output-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&ret);
+        std::fs::create_dir_all(&ret).unwrap();
+        ret
+    }
+
+    #[test]
+    fn manifests_only_name_files_inside() {
+        let paths = parse("# a comment\nindex.html\n../outside\n/etc/passwd\nblog/../x\n\n  blog/one.html  \n");
+        assert_eq!(paths, vec![PathBuf::from("index.html"), PathBuf::from("blog/one.html")]);
+    }
+
+    #[test]
+    fn check_refuses_to_build_over_things() {
+        let project = scratch("check");
+        std::fs::create_dir_all(project.join("src")).unwrap();
+        assert!(check(&project, &[&project.join("src")]).is_err()); // the output contains the source
+        assert!(check(&project, &[&project]).is_err()); // sitix . .
+        let output = project.join("out");
+        std::fs::create_dir_all(&output).unwrap();
+        assert_eq!(check(&output, &[&project.join("src")]), Ok(vec![])); // empty is fine
+        std::fs::write(output.join("notes.txt"), "mine").unwrap();
+        assert!(check(&output, &[]).is_err()); // files, but no manifest
+        std::fs::write(output.join(MANIFEST), "").unwrap();
+        assert_eq!(check(&output, &[]), Ok(vec![])); // adopted, with nothing to clean
+        write_manifest(&output, &[Path::new("notes.txt")]).unwrap();
+        assert_eq!(check(&output, &[]), Ok(vec![PathBuf::from("notes.txt")]));
+        std::fs::remove_dir_all(&project).unwrap();
+    }
+
+    #[test]
+    fn clean_removes_only_what_was_generated() {
+        let output = scratch("clean");
+        for path in ["CNAME", "blog/one.html", "blog/2024/two.html", "photos/cat.jpg", "photos/mine.txt"] {
+            std::fs::create_dir_all(output.join(path).parent().unwrap()).unwrap();
+            std::fs::write(output.join(path), "x").unwrap();
+        }
+        clean(&output, &parse("blog/one.html\nblog/2024/two.html\nphotos/cat.jpg\ngone.html")).unwrap();
+        assert!(output.join("CNAME").is_file());
+        assert!(!output.join("blog").exists()); // emptied, so removed
+        assert!(output.join("photos/mine.txt").is_file());
+        assert!(!output.join("photos/cat.jpg").exists());
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+}
@@ -1,5 +1,6 @@
 /*  Rasta: templating language in Rust, meant for speed and verbosity and JSON-ey-ness.
-    Any files can be Rasta - it's determined by the opening flag ("[=]") and closing flag ("[/]").
+    Any files can be Rasta - it's determined by the opening flag ("[=]") and closing flag ("[/]"). The opening flag has to be the first thing
+    in the file, apart from whitespace and comments; everything else is copied through untouched.
 
 [=-]
     File content here!
@@ -7,7 +8,8 @@
 
     Like with Liquid, Rasta uses enclosed commands. They are like so:
 [# Rasta Comment ]
-    The first character after the opening [ is the control character. It can be "#" (comment), "^" (variable read), "=" (variable set), "!" (template set).
+    The first character after the opening [ is the control character. It can be "#" (comment), "^" (variable read), "=" (variable set), "!" (template set),
+    "%" (verbatim), "@" (function call), "*" (loop).
     If there is a dash right before the closing ], WITHOUT a space, and a [/] like
 [^ variable_with_a_default_value -] default value: used if the variable is not defined [/]
     then everything between the opening [ -] and closing [/] will be considered data. The opening flag defaults to content.
@@ -37,12 +39,51 @@
     Without the [^hello], "The string stored in world is: [^world]" will never be displayed. This is because most templates render
     only [^content] and don't bother with, say, [^content.hello].
 
+    A backslash escapes a bracket or another backslash, and is removed from the output. Use \[ to write a literal [ in page text (or \] inside
+    a tag), and \\ for a literal backslash; any other backslash, like the ones in "a\nb" or /\d+/, comes out as written.
+    For bigger chunks of code - JavaScript arrays, CSS attribute selectors - use a verbatim block:
+[%-]
+    let things = [1, 2, 3]; input[type="text"] { color: red; }
+[/%]
+    Nothing between [%-] and the first [/%] is lexed, so tags and backslashes inside it come out exactly as written.
+
+    Whitespace between tags comes out exactly as written. To get rid of it, put a ~ right after the opening [ to strip all the whitespace
+    before the tag, or right before the closing ] to strip all the whitespace after it:
+<ul>
+    [~^first_item~]
+    [~^second_item-~] this default value has no leading space [~/~]
+</ul>
+    Label values are never trimmed, so a variable that starts or ends with spaces renders with them.
+
+    Function calls run something built into sitix and render whatever it returns. The first word is the function, the rest are its arguments:
+<link rel="stylesheet" href="[@asset_url /css/site.css]">
+    Extended calls ([@function args-] body [/]) also get their rendered body. The body of [@highlight language-] is code, so it isn't
+    lexed at all: everything up to the first [/] is passed on exactly as written, brackets and backslashes included.
+
+    Loops render their body once for every variable inside another one, in the order they were set:
+[=-]
+    [=fruits-] [=a apple] [=b banana] [/]
+    <ul>[*fruit fruits-]<li>[^fruit]</li>[/]</ul>
+[/]
+
+    Names are looked up lexically. [^a.b] finds the nearest a - in the current block, then the blocks around it, then the page, the
+    template, the site (sitix.toml), and finally the global scope - and then looks for b inside it. The nearest a wins even if there's no
+    b in it, so a variable shadows everything outside it with the same name. Setting a variable again in the same block replaces it.
+    A few prefixes skip the search: [^page.title] is always the page's own title, [^site.title] is always the one from sitix.toml, and
+    [^parent.x] looks for x starting one block further out (handy when a loop variable shadows something):
+[=item outer]
+[*item things-][^item] is inside [^parent.item][/]
+    Because of that, page, site and parent can't be used as variable names.
+
     The default template for each page is just that - "default". This requires a "default.html" in your templates directory.
-    You can use different templates with the [=template <template_name>] flag at the head of the file, like
-[=template my_template]
+    You can use different templates with a [=template <template_name>] assignment in the content, like
 [=-]
+    [=template my_template]
     Templated into my_template.html instead of default.html!
 [/]
+    sitix picks the template (and the permalink) before it renders anything, so those have to be written out, not computed. Everything
+    else the template wants to know about the page is there when it renders, as [^content.title] or [^page.title], along with
+    [^page.url] (where the page ended up) and [^page.lang].
 */
 
 
@@ -54,7 +95,7 @@ trait FancyIO {
 
     fn is_empty(&mut self) -> bool;
 
-    fn undo(&mut self); // push back the last removed character.
+    fn remaining(&self) -> usize; // how many characters are left, so a lexer can tell where it is
 
     fn read_until(&mut self, end : char) -> String { // REQUIRED BEHAVIOR: Empty the buffer up to *AND INCLUDING* the next occurrence of any character matching "end".
         // If the next byte in the buffer is a match, it should return empty and not flush it - while being somewhat inconvenient, this is necessary for reliable behavior.
@@ -69,29 +110,38 @@ trait FancyIO {
         ret
     }
 
-    fn read_until_escape(&mut self, end : char) -> String { // Same behavior as read_until, but \ will escape matches.
-        let mut ret = String::new(); // slow, lazy implementation. needs work!
-        let mut escape = false;
+    fn read_until_raw(&mut self, end : char) -> String { // Same behavior as read_until, but \ will escape matches.
+        // The backslashes are kept ("\[" produces "\["), so the text can be printed back out as written; unescape() takes them out.
+        let mut ret = String::new();
         while !self.is_empty() {
             let b = self.read_char();
-            if !escape && b == end {
+            if b == '\\' {
+                ret.push(b);
+                if self.is_empty() {
+                    break;
+                }
+                ret.push(self.read_char());
+                continue;
+            }
+            if b == end {
                 break;
             }
-            escape = b == '\\';
             ret.push(b);
         }
         ret
     }
 
-    fn trim(&mut self) { // purge whitespace until it reaches a non-whitespace character
-        if self.is_empty() {
-            return; // short circuit
-        }
-        let mut b : char = self.read_char();
-        while b.is_whitespace() && !self.is_empty() {
-            b = self.read_char();
+    fn read_until_str(&mut self, end : &str) -> String { // Empty the buffer up to and including the next occurrence of the literal string "end". No escapes.
+        // If "end" never shows up, this dumps the entire buffer.
+        let mut ret = String::new();
+        while !self.is_empty() {
+            ret.push(self.read_char());
+            if ret.ends_with(end) {
+                ret.truncate(ret.len() - end.len());
+                break;
+            }
         }
-        self.undo();
+        ret
     }
 
     fn dump(&mut self) -> String { // THIS IS THE DEFAULT WAY OF DUMPING, BUT IT IS BAD! INSTEAD, OVERRIDE THIS WITH A CLEANER IMPLEMENTATION!
@@ -105,13 +155,11 @@ trait FancyIO {
 
 
 struct FancyFile {
-    data : Vec<char>,
-    last : char
+    data : Vec<char>
 }
 
 struct FancyString {
-    data : Vec<char>,
-    last : char
+    data : Vec<char>
 }
 
 
@@ -125,8 +173,7 @@ impl FancyFile {
         let mut v : Vec<char> = s.chars().collect();
         v.reverse();
         Ok(Self {
-            data : v,
-            last : '\0'
+            data : v
         })
     }
 }
@@ -136,8 +183,7 @@ impl FancyString {
         let mut v : Vec<char> = data.chars().collect();
         v.reverse();
         Self {
-            data : v,
-            last : '\0'
+            data : v
         }
     }
 }
@@ -145,36 +191,38 @@ impl FancyString {
 
 impl FancyIO for FancyFile {
     fn read_char(&mut self) -> char {
-        self.last = self.data.pop().unwrap();
-        self.last
+        self.data.pop().unwrap()
     }
 
     fn is_empty(&mut self) -> bool {
         self.data.len() == 0
     }
 
-    fn undo(&mut self) {
-        self.data.push(self.last);
+    fn remaining(&self) -> usize {
+        self.data.len()
     }
 }
 
 
 impl FancyIO for FancyString {
     fn read_char(&mut self) -> char {
-        self.last = self.data.pop().unwrap();
-        self.last
+        self.data.pop().unwrap()
     }
 
     fn is_empty(&mut self) -> bool {
         self.data.len() == 0
     }
 
-    fn undo(&mut self) {
-        self.data.push(self.last);
+    fn remaining(&self) -> usize {
+        self.data.len()
     }
 }
 
 
+const RAW_FUNCTIONS : &[&str] = &["highlight"]; // functions whose extended body is code, not Rasta: it's read as written, up to the first [/]
+// There's no way to escape that [/], since backslashes in the body are kept too, so code that contains one can't go in these blocks.
+
+
 #[derive(Debug)]
 pub enum LexerToken {
     PlainText (String), // regular plaintext
@@ -184,31 +232,325 @@ pub enum LexerToken {
 }
 
 
+#[derive(Debug, Clone)]
+pub enum SourceToken { // a file the way it's written, comments and all, for tools that print it back out (like sitix fmt)
+    Text (String), // exactly as written, backslashes included
+    Tag (SourceTag)
+}
+
+
+#[derive(Debug, Clone)]
+pub struct SourceTag {
+    pub control : char, // including the ones the parser never sees: # for comments, % for verbatim, / for closing tags
+    pub trim_before : bool, // [~
+    pub raw : String, // everything between the control character and the ], backslashes included
+    pub body : Option<String>, // the body of a block that's read as written (see terminator), up to (not including) the end
+    pub start : usize, // where the [ is, in characters from the start of the file
+    pub end : usize, // just past the ] (or the end of a block that's read as written)
+    pub text : String // the whole tag exactly as written, from the [ to the end, whitespace and all
+}
+
+
+impl SourceTag {
+    pub fn inside(&self) -> (String, bool) { // (what's inside, trimmed and without a trailing ~; whether there was a ~]), with backslashes kept
+        split_inside(&self.raw)
+    }
+
+    pub fn opens_block(&self) -> bool { // an extended tag, which the next unmatched [/] closes
+        !matches!(self.control, '#' | '%' | '/') && self.inside().0.ends_with('-') && self.terminator().is_none()
+    }
+
+    pub fn unterminated(&self) -> bool { // a body that runs to the end of the file, because its terminator never shows up
+        self.terminator().is_some_and(|terminator| !self.text.ends_with(terminator))
+    }
+
+    pub fn terminator(&self) -> Option<&'static str> { // what ends the body, if it's read as written instead of lexed
+        let (content, _) = split_inside(&unescape(&self.raw));
+        let content = content.strip_suffix('-')?;
+        match self.control {
+            '%' => Some("[/%]"),
+            '@' if RAW_FUNCTIONS.contains(&content.split_whitespace().next().unwrap_or("")) => Some("[/]"),
+            _ => None
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub enum SyntaxNode { // the concrete syntax tree: source tokens nested the way the blocks go, with nothing thrown away
+    Text (String),
+    Tag (SourceTag), // anything that doesn't open a block: simple tags, comments, verbatim blocks, and [/]s with nothing to close
+    Block (SourceTag, Vec<SyntaxNode>, Option<SourceTag>) // an extended tag, what's inside it, and the [/] that closes it (if there is one)
+}
+
+
+impl SyntaxNode {
+    pub fn print(&self, out : &mut String) { // write it back out exactly as it was read
+        match self {
+            SyntaxNode::Text (text) => *out += text,
+            SyntaxNode::Tag (tag) => *out += &tag.text,
+            SyntaxNode::Block (open, children, close) => {
+                *out += &open.text;
+                for child in children {
+                    child.print(out);
+                }
+                if let Some(close) = close {
+                    *out += &close.text;
+                }
+            }
+        }
+    }
+}
+
+
+fn split_inside(text : &str) -> (String, bool) {
+    let content = text.trim();
+    match content.strip_suffix('~') {
+        Some(stripped) => (stripped.trim_end().to_string(), true),
+        None => (content.to_string(), false)
+    }
+}
+
+
+pub fn unescape(raw : &str) -> String { // \[ -> [, \] -> ], \\ -> \; any other backslash (\n in a script, \d in a regex) is kept
+    let mut ret = String::new();
+    let mut chars = raw.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' && matches!(chars.peek(), Some('[' | ']' | '\\')) {
+            ret.push(chars.next().unwrap());
+        }
+        else {
+            ret.push(c);
+        }
+    }
+    ret
+}
+
+
+pub fn has_header(path : &std::path::Path) -> Result<bool, std::io::Error> { // sniff the start of a file for a Rasta header, without reading the whole thing.
+    // A header is an assignment tag ([= or [~=, with any whitespace after the [ and the ~, like the lexer allows) that comes before
+    // anything else except whitespace and comments.
+    let mut start = vec![0; 4096];
+    let mut file = std::fs::File::open(path)?;
+    let mut len = 0;
+    loop {
+        let n = file.read(&mut start[len..])?;
+        if n == 0 {
+            break;
+        }
+        len += n;
+        if len == start.len() {
+            break;
+        }
+    }
+    start.truncate(len);
+    let mut text = match std::str::from_utf8(&start) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&start[..e.valid_up_to()]).unwrap(), // we just chopped a character in half
+        Err(_) => {
+            return Ok(false); // not UTF-8, so definitely not Rasta
+        }
+    };
+    loop {
+        text = text.trim_start_matches('\u{feff}').trim_start();
+        let control = match text.strip_prefix('[') {
+            Some(tag) => tag.trim_start().strip_prefix('~').unwrap_or(tag.trim_start()).trim_start().chars().next(),
+            None => None
+        };
+        match control {
+            Some('#') => {
+                text = match text.find(']') {
+                    Some(end) => &text[end + 1..],
+                    None => {
+                        return Ok(false);
+                    }
+                };
+            },
+            control => {
+                return Ok(control == Some('='));
+            }
+        }
+    }
+}
+
+
 pub fn lexer(f : &mut std::fs::File) -> Result<Vec<LexerToken>, Box<dyn std::error::Error + 'static>> { // TODO: make this not public
     let mut buffer = FancyFile::new(f)?;
+    Ok(lex(&mut buffer)?)
+}
+
+
+pub fn lexer_str(data : &str) -> Result<Vec<LexerToken>, SyntaxError> { // same as lexer, but for things that are already in memory
+    lex(&mut FancyString::new(data))
+}
+
+
+pub fn source_tokens(data : &str) -> Vec<SourceToken> { // the tokens of a file before anything is thrown away: comments, ~s and escapes are all still there
+    scan(&mut FancyString::new(data))
+}
+
+
+pub fn syntax_tree(data : &str) -> Vec<SyntaxNode> { // lossless: print() gives back data byte for byte, even if it doesn't parse
+    let mut stack : Vec<(Option<SourceTag>, Vec<SyntaxNode>)> = vec![(None, vec![])];
+    for token in source_tokens(data) {
+        match token {
+            SourceToken::Text (text) => stack.last_mut().unwrap().1.push(SyntaxNode::Text(text)),
+            SourceToken::Tag (tag) if tag.opens_block() => stack.push((Some(tag), vec![])),
+            SourceToken::Tag (tag) if tag.control == '/' && stack.len() > 1 => {
+                let (open, children) = stack.pop().unwrap();
+                stack.last_mut().unwrap().1.push(SyntaxNode::Block(open.unwrap(), children, Some(tag)));
+            },
+            SourceToken::Tag (tag) => stack.last_mut().unwrap().1.push(SyntaxNode::Tag(tag))
+        }
+    }
+    while stack.len() > 1 { // blocks that are never closed
+        let (open, children) = stack.pop().unwrap();
+        stack.last_mut().unwrap().1.push(SyntaxNode::Block(open.unwrap(), children, None));
+    }
+    stack.pop().unwrap().1
+}
+
+
+pub fn print(nodes : &[SyntaxNode]) -> String {
+    let mut ret = String::new();
+    for node in nodes {
+        node.print(&mut ret);
+    }
+    ret
+}
+
+
+fn lex(buffer : &mut impl FancyIO) -> Result<Vec<LexerToken>, SyntaxError> {
+    cook(scan(buffer))
+}
+
+
+fn scan(buffer : &mut impl FancyIO) -> Vec<SourceToken> {
     let mut ret = vec![];
+    let total = buffer.remaining();
     while !buffer.is_empty() {
-        let plaintext = buffer.read_until_escape('[');
-        if plaintext.len() > 0 {
-            ret.push(LexerToken::PlainText(plaintext));
+        let before = buffer.remaining();
+        let text = buffer.read_until_raw('[');
+        let found = before - buffer.remaining() > text.chars().count(); // whether it stopped at a [ or ran out
+        if !text.is_empty() {
+            ret.push(SourceToken::Text(text));
         }
         if buffer.is_empty() {
+            if found {
+                ret.push(SourceToken::Text("[".to_string())); // a [ at the very end can't start a tag
+            }
             break;
         }
-        buffer.trim();
-        let control = buffer.read_char();
-        let content = buffer.read_until_escape(']').trim().to_string();
-        if control == '/' {
+        let start = total - buffer.remaining() - 1;
+        let mut text = String::from("[");
+        let mut control = buffer.read_char();
+        while control.is_whitespace() && !buffer.is_empty() {
+            text.push(control);
+            control = buffer.read_char();
+        }
+        text.push(control);
+        let mut trim_before = false;
+        if control == '~' && !buffer.is_empty() {
+            control = buffer.read_char();
+            trim_before = true;
+            while control.is_whitespace() && !buffer.is_empty() {
+                text.push(control);
+                control = buffer.read_char();
+            }
+            text.push(control);
+        }
+        let before = buffer.remaining();
+        let raw = buffer.read_until_raw(']');
+        text += &raw;
+        if before - buffer.remaining() > raw.chars().count() {
+            text.push(']');
+        }
+        let mut tag = SourceTag {
+            control,
+            trim_before,
+            raw,
+            body : None,
+            start,
+            end : 0,
+            text
+        };
+        if let Some(terminator) = tag.terminator() { // verbatim blocks and code: the body is never lexed, and ends at the first literal terminator
+            let before = buffer.remaining();
+            let body = buffer.read_until_str(terminator);
+            tag.text += &body;
+            if before - buffer.remaining() > body.chars().count() {
+                tag.text += terminator;
+            }
+            tag.body = Some(body);
+        }
+        tag.end = total - buffer.remaining();
+        ret.push(SourceToken::Tag(tag));
+    }
+    ret
+}
+
+
+fn cook(tokens : Vec<SourceToken>) -> Result<Vec<LexerToken>, SyntaxError> { // turn what's written into what the parser needs: unescape, apply the ~s, drop the comments
+    let mut ret = vec![];
+    let mut trim_next = false; // set by a trailing ~ - strip whitespace from the start of the next plaintext
+    for token in tokens {
+        let tag = match token {
+            SourceToken::Text (raw) => {
+                let mut plaintext = unescape(&raw);
+                if trim_next {
+                    plaintext = plaintext.trim_start().to_string();
+                }
+                trim_next = false;
+                if !plaintext.is_empty() {
+                    ret.push(LexerToken::PlainText(plaintext));
+                }
+                continue;
+            },
+            SourceToken::Tag (tag) => tag
+        };
+        if tag.trim_before { // leading ~ - strip whitespace from the end of the previous plaintext
+            if let Some(LexerToken::PlainText(text)) = ret.last_mut() {
+                text.truncate(text.trim_end().len());
+                if text.is_empty() {
+                    ret.pop();
+                }
+            }
+        }
+        let (content, trim_after) = split_inside(&unescape(&tag.raw));
+        if tag.unterminated() { // otherwise it swallows the rest of the file, and the error blames whatever was open around it
+            return Err(SyntaxError(format!("[{}{}] is never closed; it needs a {}", tag.control, content, tag.terminator().unwrap())));
+        }
+        trim_next = trim_after;
+        if tag.control == '/' {
+            ret.push(LexerToken::ClosingTag);
+        }
+        else if tag.control == '%' {
+            let mut body = tag.body.unwrap_or(content);
+            if trim_next {
+                body = body.trim_start().to_string();
+                trim_next = false;
+            }
+            if !body.is_empty() {
+                ret.push(LexerToken::PlainText(body));
+            }
+        }
+        else if let (Some(mut body), Some(stripped)) = (tag.body, content.strip_suffix('-')) { // a call whose body is code
+            ret.push(LexerToken::ExtTag (tag.control, stripped.trim().to_string()));
+            if trim_next {
+                body = body.trim_start().to_string();
+                trim_next = false;
+            }
+            if !body.is_empty() {
+                ret.push(LexerToken::PlainText(body));
+            }
             ret.push(LexerToken::ClosingTag);
         }
-        else if control != '#' { // don't parse comments
-            if content.len() > 0 && content.chars().last().unwrap() == '-' {
-                let mut cars = content.chars();
-                cars.next_back();
-                ret.push(LexerToken::ExtTag (control, cars.as_str().trim().to_string()));
+        else if tag.control != '#' { // don't parse comments
+            if let Some(stripped) = content.strip_suffix('-') {
+                ret.push(LexerToken::ExtTag (tag.control, stripped.trim().to_string()));
             }
             else {
-                ret.push(LexerToken::SimpleTag (control, content));
+                ret.push(LexerToken::SimpleTag (tag.control, content));
             }
         }
     }
@@ -221,6 +563,8 @@ enum Operation {
     Assignment (String, String), // write a variable
     Label (String, Option<String>), // read a variable, with optional default value (if it don't exist)
     Text (String), // this is just plaintext, to be immediately rendered
+    Call (String, String), // call a function provided by sitix, with arguments
+    Each (String, String), // render the children once for every variable inside another variable
 }
 
 
@@ -228,7 +572,7 @@ impl std::fmt::Display for Operation {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Operation::Assignment (name, value) => {
-                write!(f, "={} ({})", if name.len() > 0 { name.clone() } else { "content".to_string() }, value)
+                write!(f, "={} ({})", if !name.is_empty() { name.clone() } else { "content".to_string() }, value)
             },
             Operation::Text (text) => {
                 write!(f, "\x1b[33m{:?}\x1b[0m", text)
@@ -238,6 +582,12 @@ impl std::fmt::Display for Operation {
             },
             Operation::Label (thing, None) => {
                 write!(f, "Label \x1b[32m{}\x1b[0m, no inline default", thing)
+            },
+            Operation::Call (function, args) => {
+                write!(f, "Call \x1b[35m{}\x1b[0m ({})", function, args)
+            },
+            Operation::Each (name, list) => {
+                write!(f, "Each \x1b[32m{}\x1b[0m in \x1b[32m{}\x1b[0m", name, list)
             }
         }
     }
@@ -251,21 +601,111 @@ pub struct TreeNode {
 }
 
 
+pub type Function = Rc<dyn Fn(&str, Option<String>) -> String>; // (arguments, rendered body if the call was an extended tag) -> output
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScopeKind {
+    Global, // the root: functions live here
+    Site, // sitix.toml and everything else that's the same for every page
+    Template, // where the layout renders
+    Page, // where the page itself renders (the content scope)
+    Variable, // an ordinary [=variable]
+    Block // a loop iteration or a function body: it can see out, but nothing can see into it
+}
+
+
+pub const RESERVED : &[&str] = &["page", "site", "parent"]; // prefixes that always mean a particular scope, so variables can't use them
+
+
+#[derive(Debug)]
+pub struct SyntaxError (pub String); // a file that isn't valid Rasta
+
+
+impl std::fmt::Display for SyntaxError {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+
+impl std::error::Error for SyntaxError {}
+
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Warning { // something that's probably a mistake, but isn't stopping anything from rendering
+    pub kind : &'static str, // what sort of warning it is, for turning it off: "undefined-variable", "unused-assignment" or "reserved-name"
+    pub message : String
+}
+
+
+impl Warning {
+    pub fn new(kind : &'static str, message : String) -> Self {
+        Self {
+            kind,
+            message
+        }
+    }
+}
+
+
 pub struct Scope {
     pub name : String,
+    pub kind : ScopeKind,
     parent : Option<Rc<RefCell<Scope>>>,
     children : Vec<Rc<RefCell<Scope>>>,
-    content : String
+    content : String,
+    functions : std::collections::HashMap<String, Function>,
+    warnings : RefCell<Vec<Warning>> // only used in the global scope
 }
 
 
 impl Scope {
     pub fn top() -> Self {
         Self {
-            name : "page".to_string(),
+            name : String::new(),
+            kind : ScopeKind::Global,
             parent : None,
             content : String::new(),
-            children : vec![]
+            children : vec![],
+            functions : std::collections::HashMap::new(),
+            warnings : RefCell::new(vec![])
+        }
+    }
+
+    pub fn define(&mut self, name : &str, function : Function) { // make a function callable with [@name] from this scope and everything under it
+        self.functions.insert(name.to_string(), function);
+    }
+
+    pub fn warn(&self, warning : Warning) { // warnings all end up in the global scope, once each
+        match &self.parent {
+            Some(parent) => parent.borrow().warn(warning),
+            None => {
+                let mut warnings = self.warnings.borrow_mut();
+                if !warnings.contains(&warning) {
+                    warnings.push(warning);
+                }
+            }
+        }
+    }
+
+    pub fn take_warnings(&self) -> Vec<Warning> { // everything warned about so far, from anywhere under the global scope
+        match &self.parent {
+            Some(parent) => parent.borrow().take_warnings(),
+            None => self.warnings.take()
+        }
+    }
+
+    fn warn_reserved(&self, name : &str) {
+        if RESERVED.contains(&name) {
+            self.warn(Warning::new("reserved-name", format!("{} is a reserved name, so [^{}] won't find this variable", name, name)));
+        }
+    }
+
+    fn function(&self, name : &str) -> Option<Function> {
+        match self.functions.get(name) {
+            Some(function) => Some(function.clone()),
+            None => self.parent.as_ref().and_then(|parent| parent.borrow().function(name))
         }
     }
 
@@ -274,11 +714,11 @@ impl Scope {
         for child in &self.children {
             kids.push(child.borrow().name.clone());
         }
-        println!("Scope with name {} and children {:?}", self.name, kids);
+        println!("{:?} scope with name {} and children {:?}", self.kind, self.name, kids);
     }
 
     pub fn draw_tree(&self, mut level : usize) {
-        println!("{}- {}", "  ".repeat(level), self.name);
+        println!("{}- {} ({:?})", "  ".repeat(level), self.name, self.kind);
         level += 1;
         println!("{}{}", "  ".repeat(level), self.content);
         for child in &self.children {
@@ -290,17 +730,56 @@ impl Scope {
         Rc::new(RefCell::new(self))
     }
 
-    pub fn chitlin_w(parent : Rc<RefCell<Scope>>, name : String) -> Rc<RefCell<Scope>> { // make a wrapped scope the parent of a new scope
-        let child = Scope {
-            name : name,
-            parent : Some(parent.clone()),
-            content : String::new(),
-            children : vec![]
-        }.wrap();
-        parent.borrow_mut().children.push(child.clone());
+    pub fn chitlin_w(parent : Rc<RefCell<Scope>>, name : String) -> Rc<RefCell<Scope>> { // make a wrapped scope the parent of a new variable
+        Scope::chitlin_kind_w(parent, name, ScopeKind::Variable)
+    }
+
+    pub fn chitlin_kind_w(parent : Rc<RefCell<Scope>>, name : String, kind : ScopeKind) -> Rc<RefCell<Scope>> {
+        // Setting a name that's already set in the same scope replaces it (in the same place, so loops keep their order).
+        let child = Scope::block_w(parent.clone(), kind);
+        child.borrow_mut().name = name.clone();
+        let mut parent = parent.borrow_mut();
+        match parent.children.iter().position(|c| c.borrow().name == name) {
+            Some(index) => parent.children[index] = child.clone(),
+            None => parent.children.push(child.clone())
+        }
         child
     }
 
+    pub fn block_w(parent : Rc<RefCell<Scope>>, kind : ScopeKind) -> Rc<RefCell<Scope>> { // a nameless scope that can see its parent, but isn't one of its children
+        Scope {
+            name : String::new(),
+            kind,
+            parent : Some(parent),
+            content : String::new(),
+            children : vec![],
+            functions : std::collections::HashMap::new(),
+            warnings : RefCell::new(vec![])
+        }.wrap()
+    }
+
+    pub fn set_w(scope : Rc<RefCell<Scope>>, name : &str, value : String) { // set a (possibly dotted) variable, making any scopes along the way that don't exist yet
+        let mut cursor = scope;
+        for part in name.split('.') {
+            let existing = cursor.borrow().get_child(part);
+            cursor = match existing {
+                Some(child) => child,
+                None => Scope::chitlin_w(cursor, part.to_string())
+            };
+        }
+        cursor.borrow_mut().content = value;
+    }
+
+    pub fn names(&self) -> Vec<String> { // the dotted name of every variable inside this scope, for things like completion
+        let mut ret = vec![];
+        for child in &self.children {
+            let child = child.borrow();
+            ret.push(child.name.clone());
+            ret.extend(child.names().into_iter().map(|name| format!("{}.{}", child.name, name)));
+        }
+        ret
+    }
+
     fn get_child(&self, name : &str) -> Option<Rc<RefCell<Scope>>> {
         for child in &self.children {
             if child.borrow().name == name {
@@ -310,59 +789,57 @@ impl Scope {
         None
     }
 
-    fn _get(&self, rid : Vec<&str>, ind : usize) -> Option<String> { // rid will be a vector like ["content", "test", "urmom"].
-        // If ind < rid.len - 1, find the child scope referred to by rid[ind] and call that scope's _get, incrementing ind and passing rid without change. 
-        // If ind == rid.len - 1, intelligently return whatever content is referred to by that child scope.
-        let child_scope = match self.get_child(rid[ind]) {
-            Some(scope) => scope,
-            None => {
-                return None;
-            }
-        };
-        if ind < rid.len() - 1 {
-            child_scope.borrow()._get(rid, ind + 1)
+    /*  Variable resolution. A name like a.b.c is looked up in three steps:
+        1. Find a: the nearest scope, starting with this one and going out through its parents, that has a variable named a. For a page
+           that's local, then enclosing blocks, then the page, template, site and global scopes, in that order.
+        2. Go down through b and c from there. The nearest a always wins, even if it has no b in it: an inner variable shadows the
+           whole of an outer one with the same name.
+        3. Otherwise, there's nothing there.
+        Three first parts are special, and are never looked up as variables: page. starts at the page's content scope (so it works in
+        templates too), site. starts at the site scope, and parent. skips this scope and resolves the rest from the one around it (so
+        parent.item gets at an item that a loop variable shadows). Inside a single scope, setting a variable again replaces it.
+    */
+    fn resolve<T>(&self, parts : &[&str], found : &dyn Fn(&Scope) -> Option<T>) -> Option<T> {
+        match parts[0] {
+            "page" => self.with_kind(ScopeKind::Page, &parts[1..], found),
+            "site" => self.with_kind(ScopeKind::Site, &parts[1..], found),
+            "parent" if parts.len() > 1 => self.parent.as_ref()?.borrow().resolve(&parts[1..], found),
+            "parent" => None,
+            _ => self.walk_up(parts, found)
         }
-        else {
-            Some(child_scope.borrow().content.clone())
+    }
+
+    fn walk_up<T>(&self, parts : &[&str], found : &dyn Fn(&Scope) -> Option<T>) -> Option<T> {
+        match self.get_child(parts[0]) {
+            Some(child) => child.borrow().descend(&parts[1..], found),
+            None => self.parent.as_ref()?.borrow().walk_up(parts, found)
         }
     }
 
-    fn walk_up(&self, target : &str) -> Option<Rc<RefCell<Scope>>> {
-        let mut cursor = self.parent.clone();
-        while cursor.is_some() {
-            println!("tuba");
-            match cursor.clone().unwrap().borrow().get_child(target) {
-                Some(_) => return cursor,
-                _ => {
-                    cursor = cursor.unwrap().borrow().parent.clone();
-                }
-            }
+    fn descend<T>(&self, parts : &[&str], found : &dyn Fn(&Scope) -> Option<T>) -> Option<T> {
+        match parts.first() {
+            Some(part) => self.get_child(part)?.borrow().descend(&parts[1..], found),
+            None => found(self)
         }
-        println!("Could not find scope referred to by {:?}.", target);
-        None
     }
 
-    pub fn get(&self, name : String) -> Option<String> {
-        let rid = name.split(".").collect::<Vec<&str>>();
-        if self.get_child(rid[0]).is_some() {
-            self._get(rid, 0)
-        }
-        else if self.name == rid[0] { // we looked into the face of the enemy...
-            // ...and saw only ourselves staring back at us.
-            if rid.len() == 1 {
-                Some(self.content.clone())
-            }
-            else {
-                self._get(rid, 1)
-            }
+    fn with_kind<T>(&self, kind : ScopeKind, parts : &[&str], found : &dyn Fn(&Scope) -> Option<T>) -> Option<T> {
+        // the nearest scope of this kind that's this one, one of its parents, or a child of one of those (the way the page is a child of the template)
+        if self.kind == kind {
+            return self.descend(parts, found);
         }
-        else {
-            println!("WALKING ON THE SUN");
-            match self.walk_up(rid[0]) {
-                Some(scope) => scope.borrow()._get(rid, 0),
-                None => None
-            }
+        if let Some(child) = self.children.iter().find(|c| c.borrow().kind == kind) {
+            return child.borrow().descend(parts, found);
         }
+        self.parent.as_ref()?.borrow().with_kind(kind, parts, found)
+    }
+
+    pub fn items(&self, name : &str) -> Option<Vec<Rc<RefCell<Scope>>>> { // the variables inside a variable, for loops
+        self.resolve(&name.split('.').collect::<Vec<&str>>(), &|scope| Some(scope.children.clone()))
+    }
+
+    pub fn get(&self, name : String) -> Option<String> {
+        self.resolve(&name.split('.').collect::<Vec<&str>>(), &|scope| Some(scope.content.clone()))
     }
 }
 
@@ -373,20 +850,63 @@ use std::cell::RefCell;
 
 impl TreeNode {
     pub fn parse(path : std::path::PathBuf) -> Result<TreeNode, Box<dyn std::error::Error + 'static>> { // this is purely a convenience function. It just calls Congeal.
-        let mut file = match std::fs::File::open(path) {
-            Ok(f) => f,
-            Err(_) => {panic!("PANIIIICCCC")}
-        };
+        // Reading the file goes wrong with an io::Error; bad Rasta goes wrong with a SyntaxError.
+        let mut file = std::fs::File::open(path)?;
         let tokens = lexer(&mut file)?;
+        Ok(TreeNode::from_tokens(&tokens)?)
+    }
+
+    pub fn parse_str(data : &str) -> Result<TreeNode, SyntaxError> { // same as parse, but for things that are already in memory
+        TreeNode::from_tokens(&lexer_str(data)?)
+    }
+
+    fn from_tokens(tokens : &[LexerToken]) -> Result<TreeNode, SyntaxError> {
         let mut tokens = tokens.iter().peekable();
-        return Ok(TreeNode::congeal(&mut tokens));
+        while tokens.len() > 1 && matches!(tokens.peek(), Some(LexerToken::PlainText (t)) if t.trim().is_empty()) { // whitespace before the header doesn't count
+            tokens.next();
+        }
+        TreeNode::congeal(&mut tokens)
     }
 
-    pub fn is_plaintext(&self) -> bool {
-        match self.operation {
-            Operation::Text (_) => true,
-            _ => false
+    pub fn assignment(&self, name : &str) -> Option<String> { // the value of a [=name value] (or [=name-] text [/]) directly inside this node, found without rendering anything
+        for child in &self.children {
+            if let Operation::Assignment (n, value) = &child.operation {
+                if n == name {
+                    if !value.trim().is_empty() {
+                        return Some(value.clone());
+                    }
+                    return Some(child.children.iter().filter_map(|c| match &c.operation { Operation::Text (t) => Some(t.as_str()), _ => None }).collect());
+                }
+            }
+        }
+        None
+    }
+
+    pub fn assignments(&self) -> Vec<String> { // the name of every variable set anywhere in this tree, in order
+        let mut ret = vec![];
+        for child in &self.children {
+            if let Operation::Assignment (name, _) = &child.operation {
+                ret.push(name.clone());
+            }
+            ret.extend(child.assignments());
+        }
+        ret
+    }
+
+    pub fn references(&self) -> std::collections::HashSet<String> { // every part of every name read anywhere in this tree: [^a.b] reads a and b
+        let mut ret = std::collections::HashSet::new();
+        for child in &self.children {
+            match &child.operation {
+                Operation::Label (name, _) | Operation::Each (_, name) => ret.extend(name.split('.').map(|part| part.to_string())),
+                _ => {}
+            }
+            ret.extend(child.references());
         }
+        ret
+    }
+
+    pub fn is_plaintext(&self) -> bool {
+        matches!(self.operation, Operation::Text (_))
     }
 
     pub fn plaintext(&self) -> String {
@@ -396,66 +916,77 @@ impl TreeNode {
         }
     }
 
-    pub fn congeal(items : &mut Peekable<Iter<'_, LexerToken>>) -> TreeNode {
+    pub fn congeal(items : &mut Peekable<Iter<'_, LexerToken>>) -> Result<TreeNode, SyntaxError> {
         let me = match items.next() {
             Some(thing) => thing,
             None => {
-                return TreeNode {
+                return Ok(TreeNode {
                     operation : Operation::Text(String::new()),
                     children : vec![]
-                }
+                });
             }
         };
-        match me {
+        Ok(match me {
             LexerToken::PlainText (t) => {
                 TreeNode::new_from_op(Operation::Text(t.clone()))
             },
             LexerToken::ExtTag (control, data) => {
                 let mut childrets = vec![];
                 loop {
-                    if
-                        match items.peek() {
-                            Some(LexerToken::ClosingTag) => true,
-                            _ => false
-                        }
-                    {
-                        items.next(); // consume, but ignore, the closing tag
-                        break; // break the childret loop
+                    match items.peek() {
+                        Some(LexerToken::ClosingTag) => {
+                            items.next(); // consume, but ignore, the closing tag
+                            break; // break the childret loop
+                        },
+                        None => {
+                            return Err(SyntaxError(format!("[{}{}-] is never closed; it needs a [/]", control, data)));
+                        },
+                        _ => {}
                     }
-                    childrets.push(TreeNode::congeal(items));
+                    childrets.push(TreeNode::congeal(items)?);
                 }
+                let operation = match TreeNode::make_op(*control, data)? {
+                    Operation::Label (name, None) if childrets.is_empty() => Operation::Label(name, Some(String::new())), // [^x-][/] has a default: nothing
+                    operation => operation
+                };
                 TreeNode {
-                    operation : TreeNode::make_op(*control, data),
+                    operation,
                     children : childrets
                 }
             },
             LexerToken::SimpleTag (control, data) => {
                 TreeNode {
-                    operation : TreeNode::make_op(*control, data),
+                    operation : TreeNode::make_op(*control, data)?,
                     children : vec![]
                 }
             },
-            _ => {
-                panic!("THIS IS SO STUPID");
+            LexerToken::ClosingTag => {
+                return Err(SyntaxError("there's a [/] that doesn't close anything".to_string()));
             }
-        }
+        })
     }
 
-    fn make_op(control : char, data : &str) -> Operation {
-        let mut data = FancyString::new(data);
-        match control {
+    fn make_op(control : char, raw : &str) -> Result<Operation, SyntaxError> {
+        let mut data = FancyString::new(raw);
+        Ok(match control {
             '=' => {
                 Operation::Assignment(data.read_until(' ').trim().to_string(), data.dump().trim().to_string())
             },
             '^' => {
                 let name = data.read_until(' ').trim().to_string();
                 let dump = data.dump().trim().to_string();
-                Operation::Label(name, if dump.len() > 0 { Some(dump) } else { None })
+                Operation::Label(name, if !dump.is_empty() { Some(dump) } else { None })
+            },
+            '@' => {
+                Operation::Call(data.read_until(' ').trim().to_string(), data.dump().trim().to_string())
+            },
+            '*' => {
+                Operation::Each(data.read_until(' ').trim().to_string(), data.dump().trim().to_string())
             },
             _ => {
-                panic!("TODO: proper error handling");
+                return Err(SyntaxError(format!("[{}{}] isn't a tag Rasta knows; if it's meant to be text, write \\[{}{}]", control, raw, control, raw)));
             }
-        }
+        })
     }
 
     fn new_from_op(operation : Operation) -> Self {
@@ -481,6 +1012,7 @@ impl TreeNode {
         for child in &self.children {
             match child.operation.clone() {
                 Operation::Assignment (name, value) => {
+                    scope.borrow().warn_reserved(&name);
                     let child_scope = Scope::chitlin_w(scope.clone(), name);
                     child_scope.borrow_mut().content = if value.trim() == "" { child.render(child_scope.clone()).to_string() } else { value };
                 },
@@ -492,22 +1024,293 @@ impl TreeNode {
                     ret += &text;
                 },
                 Operation::Label (variable, default) => {
-                    let dat : Option<String> = scope.borrow().get(variable);
+                    let dat : Option<String> = scope.borrow().get(variable.clone());
                     ret += match dat {
                         Some(data) => data,
                         None => {
                             match default {
                                 Some(data) => data,
                                 None => {
-                                    child.render(scope.clone())
+                                    if child.children.is_empty() { // [^x], as opposed to [^x default] or [^x-] default [/]
+                                        scope.borrow().warn(Warning::new("undefined-variable", format!("[^{}] is undefined; give it a default like [^{}-][/] if that's fine", variable, variable)));
+                                    }
+                                    child.render(Scope::block_w(scope.clone(), ScopeKind::Block)) // so a default can't clobber what's around it
                                 }
                             }
                         }
-                    }.trim()
+                    }.as_str()
+                },
+                Operation::Call (function, args) => {
+                    let body = if child.children.is_empty() { None } else { Some(child.render(Scope::block_w(scope.clone(), ScopeKind::Block))) };
+                    let function = scope.borrow().function(&function);
+                    match function {
+                        Some(function) => {
+                            ret += &function(&args, body);
+                        },
+                        None => {
+                            ret += &body.unwrap_or_default(); // unknown functions just render whatever's inside them
+                        }
+                    }
+                },
+                Operation::Each (name, list) => {
+                    scope.borrow().warn_reserved(&name);
+                    let items = scope.borrow().items(&list);
+                    if let Some(items) = items {
+                        for item in items {
+                            let block = Scope::block_w(scope.clone(), ScopeKind::Block);
+                            let alias = Scope::chitlin_w(block.clone(), name.clone());
+                            alias.borrow_mut().content = item.borrow().content.clone();
+                            alias.borrow_mut().children = item.borrow().children.clone();
+                            ret += &child.render(block);
+                        }
+                    }
                 }
             }
         }
         scope.borrow_mut().content = ret.clone();
         ret
     }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(page : &str) -> String { // render a page the way sitix does, minus the layout
+        let global = Scope::top().wrap();
+        let site = Scope::chitlin_kind_w(global, "site".to_string(), ScopeKind::Site);
+        Scope::set_w(site.clone(), "title", "My Site".to_string());
+        let template = Scope::block_w(site, ScopeKind::Template);
+        let content = Scope::chitlin_kind_w(template, "content".to_string(), ScopeKind::Page);
+        TreeNode::parse_str(page).unwrap().render(content)
+    }
+
+    fn render_with_layout(page : &str, layout : &str) -> String {
+        let global = Scope::top().wrap();
+        let site = Scope::chitlin_kind_w(global, "site".to_string(), ScopeKind::Site);
+        Scope::set_w(site.clone(), "title", "My Site".to_string());
+        let template = Scope::block_w(site, ScopeKind::Template);
+        let content = Scope::chitlin_kind_w(template.clone(), "content".to_string(), ScopeKind::Page);
+        TreeNode::parse_str(page).unwrap().render(content);
+        TreeNode::parse_str(layout).unwrap().render(template)
+    }
+
+    #[test]
+    fn local_variables() {
+        assert_eq!(render("[=-][=x hello][^x][/]"), "hello");
+    }
+
+    #[test]
+    fn nested_variables() {
+        assert_eq!(render("[=-][=a-][=b-][=c deep][/][/][^a.b.c][/]"), "deep");
+    }
+
+    #[test]
+    fn enclosing_blocks() {
+        assert_eq!(render("[=-][=x outer][=a-][=b-][^x][/][/][^a.b][/]"), "outer");
+    }
+
+    #[test]
+    fn reading_from_inside_a_variable() {
+        assert_eq!(render("[=-][=hello-][=world Foo][^world] [^hello.world][/][^hello][/]"), "Foo Foo");
+    }
+
+    #[test]
+    fn site_variables() {
+        assert_eq!(render("[=-][^title] [^site.title][/]"), "My Site My Site");
+    }
+
+    #[test]
+    fn local_shadows_site() {
+        assert_eq!(render("[=-][=title Mine][^title] [^site.title][/]"), "Mine My Site");
+    }
+
+    #[test]
+    fn site_prefix_ignores_variables_named_site() {
+        assert_eq!(render("[=-][=site-][=title nope][/][^site.title][/]"), "My Site");
+    }
+
+    #[test]
+    fn nearest_definition_shadows_completely() {
+        // the inner a has no b, and that's the answer: no falling through to the outer a.b
+        assert_eq!(render("[=-][=a-][=b outer][/][=box-][=a inner][^a.b-]missing[/][/][^box][/]"), "missing");
+    }
+
+    #[test]
+    fn reassignment_replaces() {
+        assert_eq!(render("[=-][=x one][=x two][^x][/]"), "two");
+    }
+
+    #[test]
+    fn reassignment_keeps_loop_order() {
+        assert_eq!(render("[=-][=list-][=a 1][=b 2][/][=list-][=a 1][=b 2][=a 3][/][*i list-][^i][/][/]"), "32");
+    }
+
+    #[test]
+    fn page_prefix_from_the_page() {
+        assert_eq!(render("[=-][=title Hello][=box-][=title Inner][^page.title][/][^box][/]"), "Hello");
+    }
+
+    #[test]
+    fn page_prefix_from_the_layout() {
+        assert_eq!(render_with_layout("[=-][=title Hello]body[/]", "[=-][=title Layout][^page.title] [^title] [^content.title] [^page][/]"), "Hello Layout Hello body");
+    }
+
+    #[test]
+    fn layout_sees_site() {
+        assert_eq!(render_with_layout("[=-]body[/]", "[=-][^title] [^content][/]"), "My Site body");
+    }
+
+    #[test]
+    fn page_prefix_with_nothing_there() {
+        assert_eq!(render("[=-][^page.missing-]default[/][/]"), "default");
+    }
+
+    #[test]
+    fn loops() {
+        assert_eq!(render("[=-][=fruits-][=a apple][=b banana][/][*fruit fruits-]<[^fruit]>[/][/]"), "<apple><banana>");
+    }
+
+    #[test]
+    fn loop_items_have_insides() {
+        assert_eq!(render("[=-][=people-][=a-]Ann[=age 30][/][/][*p people-][^p] [^p.age][/][/]"), "Ann 30");
+    }
+
+    #[test]
+    fn loop_variable_shadows_and_parent_unshadows() {
+        assert_eq!(render("[=-][=item outer][=things-][=a 1][=b 2][/][*item things-][^item]/[^parent.item] [/][^item][/]"), "1/outer 2/outer outer");
+    }
+
+    #[test]
+    fn parent_from_an_assignment() {
+        assert_eq!(render("[=-][=x outer][=box-][=x inner][^x] [^parent.x][/][^box][/]"), "inner outer");
+    }
+
+    #[test]
+    fn parent_parent() {
+        assert_eq!(render("[=-][=x 1][=a-][=x 2][=b-][=x 3][^x][^parent.x][^parent.parent.x][/][^b][/][^a][/]"), "321");
+    }
+
+    #[test]
+    fn parent_alone_is_nothing() {
+        assert_eq!(render("[=-][^parent-]none[/][/]"), "none");
+    }
+
+    #[test]
+    fn loop_blocks_stay_private() {
+        // loop iterations and function bodies aren't variables, so they don't show up as items of what's around them
+        assert_eq!(render("[=-][=list-][=a 1][*x other-][/][@nothing-]body[/][/][*i list-][^i];[/][/]"), "1;");
+    }
+
+    #[test]
+    fn defaults_leave_the_scope_alone() {
+        assert_eq!(render("[=-]before [^missing-][=x inside]default[/] [^x-]unset[/][/]"), "before default unset");
+    }
+
+    #[test]
+    fn missing_variables() {
+        assert_eq!(render("[=-][^nope][^nope.deeper]![/]"), "!");
+    }
+
+    fn warnings(page : &str) -> Vec<&'static str> {
+        let global = Scope::top().wrap();
+        let content = Scope::chitlin_kind_w(global.clone(), "content".to_string(), ScopeKind::Page);
+        TreeNode::parse_str(page).unwrap().render(content);
+        let kinds = global.borrow().take_warnings().iter().map(|w| w.kind).collect();
+        kinds
+    }
+
+    #[test]
+    fn undefined_variables_warn() {
+        assert_eq!(warnings("[=-][^typo][^typo][/]"), vec!["undefined-variable"]);
+    }
+
+    #[test]
+    fn defaults_mean_no_warning() {
+        assert!(warnings("[=-][^a b][^c-]d[/][^e-][/][=f 1][^f][/]").is_empty());
+    }
+
+    #[test]
+    fn reserved_names_warn() {
+        assert_eq!(warnings("[=-][=site x][*parent y-][/][/]"), vec!["reserved-name", "reserved-name"]);
+    }
+
+    #[test]
+    fn assignments_and_references() {
+        let tree = TreeNode::parse_str("[=-][=a-][=b 1][/][*i list.items-][^i.name][/][/]").unwrap();
+        assert_eq!(tree.assignments(), vec!["a", "b"]);
+        let mut references : Vec<String> = tree.references().into_iter().collect();
+        references.sort();
+        assert_eq!(references, vec!["i", "items", "list", "name"]);
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert!(TreeNode::parse_str("[=-] never closed").is_err());
+        assert!(TreeNode::parse_str("[=-][=a-] also never closed[/]").is_err());
+        assert!(TreeNode::parse_str("[/]").is_err());
+        assert!(TreeNode::parse_str("[=-][&what][/]").is_err());
+        assert!(TreeNode::parse_str("[=-]\\[&fine][/]").is_ok());
+        assert_eq!(TreeNode::parse_str("[=-] hi [%-] raw [/]").unwrap_err().0, "[%-] is never closed; it needs a [/%]");
+    }
+
+    #[test]
+    fn escapes_trims_and_verbatim() {
+        assert_eq!(render("[=-]a \\[b\\] \\\\ [~^x-~] d [~/] e[%-] [^raw] [/%][/]"), "a [b] \\d e [^raw] ");
+    }
+
+    #[test]
+    fn headers_can_have_spaces() {
+        let directory = std::env::temp_dir().join(format!("sitix-header-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let cases = [("[ =-]spaced header[/]", true), ("\u{feff}[ # note ]\n[~ =x-]y[/][=-][/]", true), ("[~=-][/]", true), ("[ # just a comment ]", false), ("<p>[=x]</p>", false)];
+        for (i, (text, header)) in cases.iter().enumerate() {
+            let path = directory.join(format!("{}.html", i));
+            std::fs::write(&path, text).unwrap();
+            assert_eq!(has_header(&path).unwrap(), *header, "{:?}", text);
+        }
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(render("[ =-][~ =x-]spaced header[/][ ^x][/]"), "spaced header");
+    }
+
+    #[test]
+    fn other_backslashes_are_kept() {
+        assert_eq!(render("[=-]<script>var s = \"a\\nb\"; var r = /\\d+/;</script> C:\\Users[/]"), "<script>var s = \"a\\nb\"; var r = /\\d+/;</script> C:\\Users");
+    }
+
+    #[test]
+    fn source_tokens_keep_everything() {
+        let tokens = source_tokens("[=-][# note ~] \\[x[%-] [^y] [/%][/]");
+        let tags : Vec<(char, bool)> = tokens.iter().filter_map(|t| match t { SourceToken::Tag (tag) => Some((tag.control, tag.body.is_some())), _ => None }).collect();
+        assert_eq!(tags, vec![('=', false), ('#', false), ('%', true), ('/', false)]);
+        assert!(matches!(&tokens[2], SourceToken::Text (text) if text == " \\[x"));
+    }
+
+    #[test]
+    fn syntax_tree_round_trips() {
+        for text in ["\u{feff}[# note ]\r\n[ =-]\n  a \\[b\\] [~^ x -~] y [~/] [%-] [^raw] [/%][/]\n", "[=-][=a-][# never closed", "[/][^x [%-] no end", "[=-]\\", "text [", "a [  "] {
+            assert_eq!(print(&syntax_tree(text)), text);
+        }
+        let tree = syntax_tree("[=-][=a-]x[/][# c][/]");
+        assert!(matches!(&tree[..], [SyntaxNode::Block (_, children, Some(_))] if matches!(&children[..], [SyntaxNode::Block (..), SyntaxNode::Tag (tag)] if tag.control == '#')));
+    }
+
+    #[test]
+    fn functions_are_found_from_anywhere() {
+        let global = Scope::top().wrap();
+        global.borrow_mut().define("shout", Rc::new(|args : &str, _| args.trim().to_uppercase()));
+        let content = Scope::chitlin_kind_w(global, "content".to_string(), ScopeKind::Page);
+        assert_eq!(TreeNode::parse_str("[=-][=a-][@shout hi][/][^a][/]").unwrap().render(content), "HI");
+    }
+
+    #[test]
+    fn highlight_bodies_are_read_as_written() {
+        let global = Scope::top().wrap();
+        global.borrow_mut().define("highlight", Rc::new(|_ : &str, body : Option<String>| body.unwrap_or_default()));
+        let content = Scope::chitlin_kind_w(global, "content".to_string(), ScopeKind::Page);
+        let text = "[=-][@highlight rust-]let a = [1]; let s = \"x\\ty\\\\\"; [^b][/][/]";
+        assert_eq!(TreeNode::parse_str(text).unwrap().render(content), "let a = [1]; let s = \"x\\ty\\\\\"; [^b]");
+        assert_eq!(print(&syntax_tree(text)), text);
+    }
+}
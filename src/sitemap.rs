@@ -0,0 +1,113 @@
+/*  sitemap.xml generation. Every rendered HTML page is listed with its absolute URL (base_url from sitix.toml, which is required for this)
+    and the date its source file was last modified. Pages can opt out with [=sitemap false] in their content.
+    Past 50,000 URLs (the most a single sitemap is allowed to hold) the pages are split across sitemap-1.xml, sitemap-2.xml, ... and
+    sitemap.xml becomes a sitemap index pointing at them. Turn the whole thing off with sitemap = false in sitix.toml.
+*/
+
+use std::path::{Path, PathBuf};
+
+
+pub const MAX_URLS : usize = 50000;
+
+
+pub fn page_url(base_url : &str, path : &Path) -> String { // absolute URL for a file in the output directory. index.html files get a directory URL.
+    let mut parts : Vec<String> = path.components().map(|c| percent_encode(&c.as_os_str().to_string_lossy())).collect();
+    if parts.last().is_some_and(|last| last == "index.html") {
+        parts.pop();
+        parts.push(String::new());
+    }
+    format!("{}/{}", base_url.trim_end_matches('/'), parts.join("/"))
+}
+
+
+fn percent_encode(segment : &str) -> String {
+    let mut ret = String::new();
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            ret.push(byte as char);
+        }
+        else {
+            ret += &format!("%{:02X}", byte);
+        }
+    }
+    ret
+}
+
+
+pub fn xml_escape(text : &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+
+pub fn listed(setting : Option<String>) -> bool { // what a page's [=sitemap ...] says
+    setting.is_none_or(|setting| setting.trim() != "false")
+}
+
+
+pub fn build(base_url : &str, pages : &[(PathBuf, i64, bool)], max_urls : usize) -> Vec<(PathBuf, String)> {
+    // (output path, last modified as unix time, whether it's listed) -> files to write, with at most max_urls in each sitemap
+    let pages : Vec<&(PathBuf, i64, bool)> = pages.iter().filter(|(_, _, listed)| *listed).collect();
+    let sitemaps : Vec<String> = pages.chunks(max_urls).map(|chunk| {
+        let mut ret = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+        for (path, modified, _) in chunk {
+            ret += &format!("  <url>\n    <loc>{}</loc>\n    <lastmod>{}</lastmod>\n  </url>\n", xml_escape(&page_url(base_url, path)), crate::dates::w3c_date(*modified));
+        }
+        ret += "</urlset>\n";
+        ret
+    }).collect();
+    if sitemaps.len() <= 1 {
+        let only = sitemaps.into_iter().next().unwrap_or_else(|| String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n</urlset>\n"));
+        return vec![(PathBuf::from("sitemap.xml"), only)];
+    }
+    let mut index = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    let mut ret = vec![];
+    for (number, sitemap) in sitemaps.into_iter().enumerate() {
+        let path = PathBuf::from(format!("sitemap-{}.xml", number + 1));
+        index += &format!("  <sitemap>\n    <loc>{}</loc>\n  </sitemap>\n", xml_escape(&page_url(base_url, &path)));
+        ret.push((path, sitemap));
+    }
+    index += "</sitemapindex>\n";
+    ret.push((PathBuf::from("sitemap.xml"), index));
+    ret
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_are_absolute_and_encoded() {
+        assert_eq!(page_url("https://example.com/", Path::new("index.html")), "https://example.com/");
+        assert_eq!(page_url("https://example.com", Path::new("blog/index.html")), "https://example.com/blog/");
+        assert_eq!(page_url("https://example.com/docs", Path::new("a b/ü.html")), "https://example.com/docs/a%20b/%C3%BC.html");
+        assert_eq!(page_url("", Path::new("about/index.html")), "/about/");
+        assert_eq!(percent_encode("a-b_c.d~e&f"), "a-b_c.d~e%26f");
+    }
+
+    #[test]
+    fn opted_out_pages_are_left_out() {
+        assert!(listed(None));
+        assert!(listed(Some("true".to_string())));
+        assert!(!listed(Some(" false ".to_string())));
+        let pages = [(PathBuf::from("index.html"), 0, true), (PathBuf::from("secret.html"), 0, false)];
+        let files = build("https://example.com", &pages, MAX_URLS);
+        assert_eq!(files.len(), 1);
+        assert!(files[0].1.contains("<loc>https://example.com/</loc>\n    <lastmod>1970-01-01</lastmod>"));
+        assert!(!files[0].1.contains("secret"));
+        assert!(build("https://example.com", &pages[1..], MAX_URLS)[0].1.ends_with("<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n</urlset>\n"));
+    }
+
+    #[test]
+    fn big_sites_get_an_index() {
+        let pages : Vec<(PathBuf, i64, bool)> = (0..5).map(|i| (PathBuf::from(format!("{}.html", i)), 0, true)).collect();
+        let files = build("https://example.com", &pages, 2);
+        let paths : Vec<&str> = files.iter().map(|(path, _)| path.to_str().unwrap()).collect();
+        assert_eq!(paths, ["sitemap-1.xml", "sitemap-2.xml", "sitemap-3.xml", "sitemap.xml"]);
+        assert_eq!(files[2].1.matches("<url>").count(), 1);
+        assert!(files[1].1.contains("/2.html") && files[1].1.contains("/3.html"));
+        assert!(files[3].1.contains("<sitemapindex"));
+        assert!(files[3].1.contains("<loc>https://example.com/sitemap-3.xml</loc>"));
+        assert_eq!(build("https://example.com", &pages[..2], 2).len(), 1);
+    }
+}
@@ -0,0 +1,190 @@
+/*  Taxonomies: tags, categories, or anything else pages can be sorted into. A page joins terms with a comma-separated assignment in its
+    content, like [=tags rust, web dev]. Configure them in sitix.toml (this is the default):
+
+[[taxonomies]]
+name = "tags"          # the assignment pages use
+template = "taxonomy"  # template for each term's listing page. If there's no such template, no listing pages are made.
+path = "tags"          # listing pages go to tags/<term>/index.html
+
+[[taxonomies]]
+name = "categories"
+template = "taxonomy"
+path = "categories"
+
+    Every page can see every term, which is what tag clouds are made of:
+[*tag site.taxonomies.tags-]<a href="[^tag.url]">[^tag.name]</a> ([^tag.count]) [/]
+    A listing page is rendered with its template and a term scope: term.name, term.slug, term.count, term.url, term.taxonomy, and
+    term.pages, which holds title, url, date and summary for each page with that term (newest first):
+<ul>[*post term.pages-]<li><a href="[^post.url]">[^post.title]</a></li>[/]</ul>
+    On a multilingual site every language has its own terms, counted from its own pages, and its own listing pages under its directory
+    (fr/tags/<term>/index.html), the same as pages (see i18n.rs).
+    Terms are told apart by their slug, so "Web Dev" and "web-dev" are one term, but so are "C++", "C#" and "C". That last kind of clash
+    gets a warning, since the pages end up on one listing page; give one of them a name that slugs differently.
+*/
+
+use crate::rasta::Scope;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+
+pub struct Term {
+    pub name : String, // as the first page to use it wrote it
+    pub slug : String,
+    pub language : String,
+    pub path : PathBuf, // the listing page, relative to the output directory
+    pub sources : Vec<PathBuf> // pages with this term, relative to the source directory
+}
+
+
+impl Term {
+    pub fn url(&self) -> String {
+        crate::sitemap::page_url("", &self.path)
+    }
+}
+
+
+pub struct Taxonomy {
+    pub settings : crate::config::TaxonomySettings,
+    pub terms : BTreeMap<(String, String), Term> // by language, then slug
+}
+
+
+impl Taxonomy {
+    pub fn terms<'a>(&'a self, language : &'a str) -> impl Iterator<Item = &'a Term> {
+        self.terms.values().filter(move |term| term.language == language)
+    }
+}
+
+
+pub fn slugify(name : &str) -> String { // "Web Dev" -> "web-dev"
+    let mut ret = String::new();
+    for c in name.trim().chars() {
+        if c.is_alphanumeric() {
+            ret.extend(c.to_lowercase());
+        }
+        else if !ret.ends_with('-') {
+            ret.push('-');
+        }
+    }
+    ret.trim_matches('-').to_string()
+}
+
+
+fn spelling(name : &str) -> String { // what has to match for two names with the same slug to be the same term: "Web Dev" is "web-dev", "C#" isn't "C"
+    name.to_lowercase().chars().filter(|c| !c.is_whitespace() && !"-_".contains(*c)).collect()
+}
+
+
+pub fn collect(settings : &[crate::config::TaxonomySettings], i18n : &crate::config::I18nSettings, pages : &[(PathBuf, String, &crate::rasta::TreeNode)]) -> (Vec<Taxonomy>, Vec<String>) {
+    // Find every term on every page, given (source path, language, tree) for each. This reads the assignments straight out of the tree, so
+    // it can happen before anything is rendered. Also returns warnings about different names that ended up with the same slug.
+    let mut warnings = vec![];
+    let taxonomies = settings.iter().map(|settings| {
+        let mut terms : BTreeMap<(String, String), Term> = BTreeMap::new();
+        let mut clashes = std::collections::BTreeSet::new();
+        for (source, language, tree) in pages {
+            let value = match tree.assignment(&settings.name) {
+                Some(value) => value,
+                None => continue
+            };
+            for name in value.split(',') {
+                let name = name.trim();
+                let slug = slugify(name);
+                if slug.is_empty() {
+                    continue;
+                }
+                let term = terms.entry((language.clone(), slug.clone())).or_insert_with(|| Term {
+                    name : name.to_string(),
+                    path : crate::i18n::prefix(i18n, language, settings.path.join(&slug).join("index.html")),
+                    slug,
+                    language : language.clone(),
+                    sources : vec![]
+                });
+                if spelling(name) != spelling(&term.name) && clashes.insert((language.clone(), spelling(name))) {
+                    warnings.push(format!("{}: {} {:?} and {:?} both have the slug {}, so they share a listing page", source.display(), settings.name, name, term.name, term.slug));
+                }
+                if !term.sources.contains(source) {
+                    term.sources.push(source.clone());
+                }
+            }
+        }
+        Taxonomy {
+            settings : settings.clone(),
+            terms
+        }
+    }).collect();
+    (taxonomies, warnings)
+}
+
+
+pub fn fill_scope(taxonomies : &[Taxonomy], language : &str, scope : Rc<RefCell<Scope>>) { // scope should be the site scope
+    for taxonomy in taxonomies {
+        for term in taxonomy.terms(language) {
+            let prefix = format!("taxonomies.{}.{}", taxonomy.settings.name, term.slug);
+            fill_term(taxonomy, term, scope.clone(), &prefix);
+        }
+    }
+}
+
+
+pub fn fill_term(taxonomy : &Taxonomy, term : &Term, scope : Rc<RefCell<Scope>>, prefix : &str) {
+    Scope::set_w(scope.clone(), prefix, term.sources.len().to_string());
+    Scope::set_w(scope.clone(), &format!("{}.name", prefix), term.name.clone());
+    Scope::set_w(scope.clone(), &format!("{}.slug", prefix), term.slug.clone());
+    Scope::set_w(scope.clone(), &format!("{}.count", prefix), term.sources.len().to_string());
+    Scope::set_w(scope.clone(), &format!("{}.url", prefix), term.url());
+    Scope::set_w(scope, &format!("{}.taxonomy", prefix), taxonomy.settings.name.clone());
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{I18nSettings, TaxonomySettings};
+    use crate::rasta::TreeNode;
+
+    #[test]
+    fn slugs() {
+        assert_eq!(slugify("Web Dev"), "web-dev");
+        assert_eq!(slugify("  Rust!  "), "rust");
+        assert_eq!(slugify("C++ / C#"), "c-c");
+        assert_eq!(slugify("Ünïcode Straße"), "ünïcode-straße");
+        assert_eq!(slugify("---"), "");
+    }
+
+    #[test]
+    fn terms_are_grouped_by_slug_and_language() {
+        let trees : Vec<TreeNode> = ["[=-][=tags Rust, Web Dev][/]", "[=-][=tags rust,web-dev ,][/]", "[=-][=tags Rust][/]", "[=-][=categories Rust][/]"]
+            .iter().map(|page| TreeNode::parse_str(page).unwrap()).collect();
+        let i18n = I18nSettings {
+            languages : vec!["en".to_string(), "fr".to_string()],
+            ..I18nSettings::default()
+        };
+        let pages = [
+            (PathBuf::from("a.html"), "en".to_string(), &trees[0]),
+            (PathBuf::from("b.html"), "en".to_string(), &trees[1]),
+            (PathBuf::from("fr/c.html"), "fr".to_string(), &trees[2]),
+            (PathBuf::from("d.html"), "en".to_string(), &trees[3])
+        ];
+        let (taxonomies, warnings) = collect(&[TaxonomySettings::named("tags")], &i18n, &pages);
+        assert!(warnings.is_empty());
+        let tags = &taxonomies[0];
+        let english : Vec<(&str, &str, usize, String)> = tags.terms("en").map(|term| (term.name.as_str(), term.slug.as_str(), term.sources.len(), term.url())).collect();
+        assert_eq!(english, [("Rust", "rust", 2, "/tags/rust/".to_string()), ("Web Dev", "web-dev", 2, "/tags/web-dev/".to_string())]);
+        let french : Vec<&Term> = tags.terms("fr").collect();
+        assert_eq!(french.len(), 1);
+        assert_eq!(french[0].path, PathBuf::from("fr/tags/rust/index.html"));
+        assert_eq!(french[0].sources, [PathBuf::from("fr/c.html")]);
+    }
+
+    #[test]
+    fn clashing_slugs_are_reported() {
+        let trees : Vec<TreeNode> = ["[=-][=tags C++, C][/]", "[=-][=tags C#, c][/]"].iter().map(|page| TreeNode::parse_str(page).unwrap()).collect();
+        let pages = [(PathBuf::from("a.html"), "en".to_string(), &trees[0]), (PathBuf::from("b.html"), "en".to_string(), &trees[1])];
+        let (taxonomies, warnings) = collect(&[TaxonomySettings::named("tags")], &I18nSettings::default(), &pages);
+        assert_eq!(taxonomies[0].terms.len(), 1);
+        assert_eq!(warnings, ["a.html: tags \"C\" and \"C++\" both have the slug c, so they share a listing page", "b.html: tags \"C#\" and \"C++\" both have the slug c, so they share a listing page"]);
+    }
+}